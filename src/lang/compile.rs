@@ -1,7 +1,11 @@
-use crate::lang::parser::{ self, Node, CommandNode, ExpressionNode };
-use crate::operator::Operator;
-use crate::synth::{ Synth, Id, NodeKind };
+use crate::lang::parser::{ Node, CommandNode, ExpressionNode, ConstValue, ConstArgs };
+use crate::lang::pattern;
+use crate::wav;
+use crate::operator::{ Operator, Function };
+use crate::synth::{ Synth, Id, NodeKind, VoiceSteal, Tuning, SampleData, WavetableData, Interpolation, ConvolutionData, Oversampler, Shape, ReverbLines, InputFile, poly, branch, trigger, physical, analysis, spectral };
 use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct CompileError {
@@ -9,45 +13,122 @@ pub struct CompileError {
     pub pos: Option<(usize, usize)>
 }
 
+// The contents are only read when debug printing
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum CompileErrorKind {
     UnknownFunctionName(String),
     InvalidVariableName,
     InvalidNumberOfOperatorArgs,
     InvalidArgNumber,
+    InvalidProperty(String),
+    TempoNotConstant,
+    CouldNotReadFile(PathBuf, std::io::Error),
+    CouldNotReadWave(PathBuf, hound::Error),
+    InvalidTuningFile(PathBuf, String),
     NoOutputVariables,
     ExpectedFloat,
+    ExpectedString,
+    UnexpectedString,
+    InvalidPattern(String),
+    ExpectedBody,
+    UnexpectedBody,
+    EmptyWavetable,
+    InvalidSeed,
 }
 
-pub fn compile(nodes: Vec<Node<CommandNode>>) -> Result<(Synth, Id, Id), CompileError> {
-    let mut synth = Synth::new();
-    let mut variables = HashMap::new();
-    let mut probes = Vec::new();
+/// Things that are the same for the entire file, except the
+/// sample rate, which is higher in oversampled bodies
+#[derive(Clone)]
+struct Context {
+    // In beats per minute
+    tempo: f32,
+    tuning: Rc<Tuning>,
+    // The directory that file names are relative to
+    directory: PathBuf,
+    // The seed declared in the patch
+    seed: u32,
+    // Names of input files given when running the synth, and the files
+    inputs: HashMap<String, PathBuf>,
+    // The sample rate the nodes that are compiled run at
+    sample_rate: f32,
+}
 
-    // Do the main compilation
-    for node in nodes.into_iter() {
-        // Match the different types of commands that can exist.
-        match node.kind {
-            CommandNode::Assignment(name, expr) => {
-                // Create a node tree for the expression, 
-                // then point a variable at it
-                let id = compile_expression(*expr, &mut probes, &variables, &mut synth)?;
-                variables.insert(name, id);
-            }
+impl Context {
+    fn beats_to_seconds(&self, beats: f32) -> f32 {
+        beats * 60.0 / self.tempo
+    }
+
+    fn note_to_freq(&self, midi: f32) -> f32 {
+        self.tuning.midi_to_freq(midi)
+    }
+
+    /// Opens a wave file, and checks that it has the channel. The positions
+    /// are where the file name and the channel are.
+    fn open_wave(&self, 
+                 path: &Path, 
+                 channel: Option<usize>, 
+                 pos: Option<(usize, usize)>, 
+                 channel_pos: Option<(usize, usize)>) -> Result<wav::WaveStream, CompileError> {
+        let stream = wav::WaveStream::open(path, channel).map_err(|err| CompileError {
+            kind: CompileErrorKind::CouldNotReadWave(path.to_path_buf(), err),
+            pos
+        })?;
+
+        match channel {
+            Some(channel) if channel >= stream.channels() => Err(CompileError {
+                kind: CompileErrorKind::InvalidProperty("channel".to_string()),
+                pos: channel_pos
+            }),
+            _ => Ok(stream),
         }
     }
 
-    // Compile the probes
-    while let Some((probe_id, max_size, expr)) = probes.pop() {
-        // For each probe, we compile its node tree, i.e. the node it probes.
-        // Since we compile these as a final step, they have access to every
-        // variable defined in the file, which allows them to implement
-        // cross referencing and referencing variables that are defined
-        // after them, as in reality they are compiled after every variable
-        // is added.
-        let expr_id = compile_expression(expr, &mut probes, &variables, &mut synth)?;
-        synth.add_probe(probe_id, max_size, expr_id);
+    fn read_wave(&self, 
+                 name: &str, 
+                 channel: Option<usize>, 
+                 pos: Option<(usize, usize)>, 
+                 channel_pos: Option<(usize, usize)>) -> Result<(Vec<f32>, u32), CompileError> {
+        let path = self.directory.join(name);
+        let stream = self.open_wave(&path, channel, pos, channel_pos)?;
+        stream.read_all().map_err(|err| CompileError {
+            kind: CompileErrorKind::CouldNotReadWave(path, err),
+            pos
+        })
+    }
+
+    fn read_file(&self, name: &str, pos: Option<(usize, usize)>) -> Result<(PathBuf, String), CompileError> {
+        let path = self.directory.join(name);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok((path, contents)),
+            Err(err) => Err(CompileError {
+                kind: CompileErrorKind::CouldNotReadFile(path, err),
+                pos
+            }),
+        }
     }
+}
+
+/// Compiles a patch to run at a sample rate. The seed overrides the seed declared
+/// in the patch, and the inputs are used instead of the files with those names.
+pub fn compile(nodes: Vec<Node<CommandNode>>, 
+               directory: &Path, 
+               sample_rate: f32,
+               seed: Option<u32>, 
+               inputs: &HashMap<String, PathBuf>) -> Result<(Synth, Id, Id), CompileError> {
+    let mut synth = Synth::new();
+    let mut ctx = Context {
+        tempo: 120.0,
+        tuning: Rc::new(Tuning::equal_temperament()),
+        directory: directory.to_path_buf(),
+        seed: 0,
+        inputs: inputs.clone(),
+        sample_rate,
+    };
+
+    let nodes = read_declarations(nodes, &mut ctx)?;
+    synth.seed = seed.unwrap_or(ctx.seed);
+    let variables = compile_scope(nodes, HashMap::new(), &mut synth, &ctx)?;
 
     // Get the variables used for output. 
     // These are either 'out' for mono output,
@@ -76,15 +157,350 @@ pub fn compile(nodes: Vec<Node<CommandNode>>) -> Result<(Synth, Id, Id), Compile
     Ok((synth, left, right))
 }
 
+/// Global declarations, like the tempo, are read before anything else
+/// so that it doesn't matter where in the file they are. They are then
+/// removed, so they don't become variables.
+fn read_declarations(nodes: Vec<Node<CommandNode>>, ctx: &mut Context) -> Result<Vec<Node<CommandNode>>, CompileError> {
+    let mut scale = None;
+    let mut keyboard_mapping = None;
+    let mut commands = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let CommandNode::Assignment(name, expr) = &node.kind;
+        match name.as_str() {
+            "tempo" => match expr.kind {
+                ExpressionNode::Float(tempo) if tempo > 0.0 => ctx.tempo = tempo,
+                _ => return Err(CompileError {
+                    kind: CompileErrorKind::TempoNotConstant,
+                    pos: node.pos
+                }),
+            },
+            "seed" => match expr.kind {
+                ExpressionNode::Float(seed) if seed >= 0.0 && seed.fract() == 0.0 => ctx.seed = seed as u32,
+                _ => return Err(CompileError {
+                    kind: CompileErrorKind::InvalidSeed,
+                    pos: node.pos
+                }),
+            },
+            "tuning" | "keymap" => match &expr.kind {
+                ExpressionNode::String(file) => {
+                    let file = ctx.read_file(file, expr.pos)?;
+                    if name == "tuning" {
+                        scale = Some(file);
+                    }else{
+                        keyboard_mapping = Some(file);
+                    }
+                },
+                _ => return Err(CompileError {
+                    kind: CompileErrorKind::ExpectedString,
+                    pos: expr.pos
+                }),
+            },
+            _ => commands.push(node),
+        }
+    }
+
+    if scale.is_some() || keyboard_mapping.is_some() {
+        let tuning = Tuning::from_scala(
+            scale.as_ref().map(|(_, contents)| contents.as_str()), 
+            keyboard_mapping.as_ref().map(|(_, contents)| contents.as_str()));
+        ctx.tuning = match tuning {
+            Ok(tuning) => Rc::new(tuning),
+            Err(err) => {
+                // Blame the keyboard mapping if it's the only file
+                let (path, _) = scale.or(keyboard_mapping).unwrap();
+                return Err(CompileError {
+                    kind: CompileErrorKind::InvalidTuningFile(path, err),
+                    pos: None
+                });
+            },
+        };
+    }
+
+    Ok(commands)
+}
+
+/// Compiles a list of commands, starting out with some variables
+/// that were defined outside of them, and returns all the variables
+/// once it's done.
+fn compile_scope(nodes: Vec<Node<CommandNode>>,
+                 mut variables: HashMap<String, Id>,
+                 synth: &mut Synth,
+                 ctx: &Context) -> Result<HashMap<String, Id>, CompileError> {
+    let mut probes = Vec::new();
+
+    // Do the main compilation
+    for node in nodes.into_iter() {
+        // Match the different types of commands that can exist.
+        match node.kind {
+            CommandNode::Assignment(name, expr) => {
+                // Create a node tree for the expression, 
+                // then point a variable at it
+                let id = compile_expression(*expr, &mut probes, &variables, synth, ctx)?;
+                for (output, output_id) in synth.get_named_outputs(id) {
+                    variables.insert(format!("{}.{}", name, output), output_id);
+                }
+                variables.insert(name, id);
+            }
+        }
+    }
+
+    // Compile the probes
+    while let Some((probe_id, max_size, expr)) = probes.pop() {
+        // For each probe, we compile its node tree, i.e. the node it probes.
+        // Since we compile these as a final step, they have access to every
+        // variable defined in the scope, which allows them to implement
+        // cross referencing and referencing variables that are defined
+        // after them, as in reality they are compiled after every variable
+        // is added.
+        let expr_id = compile_expression(expr, &mut probes, &variables, synth, ctx)?;
+        synth.add_probe(probe_id, max_size, expr_id);
+    }
+
+    Ok(variables)
+}
+
+fn const_arg(const_args: &ConstArgs, name: &str, default: f32) -> f32 {
+    match const_args.named.get(name) {
+        Some(Node { kind: ConstValue::Float(value), .. }) => *value,
+        _ => default,
+    }
+}
+
+fn const_arg_pos(const_args: &ConstArgs, name: &str) -> Option<(usize, usize)> {
+    const_args.named.get(name).and_then(|v| v.pos)
+}
+
+/// Gets a property that has to be a string, given in order, and where it is
+fn const_string(const_args: &ConstArgs, index: usize, pos: Option<(usize, usize)>) -> Result<Node<&str>, CompileError> {
+    match const_args.positional.get(index) {
+        Some(Node { kind: ConstValue::String(string), pos }) => Ok(Node { kind: string, pos: *pos }),
+        Some(value) => Err(CompileError {
+            kind: CompileErrorKind::ExpectedString,
+            pos: value.pos
+        }),
+        None => Err(CompileError {
+            kind: CompileErrorKind::ExpectedString,
+            pos
+        }),
+    }
+}
+
+/// Gets a property that is a list of numbers
+fn const_list(const_args: &ConstArgs, name: &str) -> Result<Option<Vec<f32>>, CompileError> {
+    match const_args.named.get(name) {
+        Some(Node { kind: ConstValue::List(values), .. }) => {
+            let mut list = Vec::with_capacity(values.len());
+            for value in values {
+                match value.kind {
+                    ConstValue::Float(value) => list.push(value),
+                    _ => return Err(CompileError {
+                        kind: CompileErrorKind::ExpectedFloat,
+                        pos: value.pos
+                    }),
+                }
+            }
+            Ok(Some(list))
+        },
+        Some(Node { kind: ConstValue::Float(value), .. }) => Ok(Some(vec![*value])),
+        Some(Node { pos, .. }) => Err(CompileError {
+            kind: CompileErrorKind::ExpectedFloat,
+            pos: *pos
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Gets the ``interp`` property
+fn const_interpolation(const_args: &ConstArgs, default: Interpolation) -> Result<Interpolation, CompileError> {
+    match const_args.named.get("interp") {
+        Some(Node { kind: ConstValue::Float(value), pos }) => {
+            Interpolation::from_number(*value as usize).ok_or(CompileError {
+                kind: CompileErrorKind::InvalidProperty("interp".to_string()),
+                pos: *pos
+            })
+        },
+        Some(Node { pos, .. }) => Err(CompileError {
+            kind: CompileErrorKind::ExpectedFloat,
+            pos: *pos
+        }),
+        None => Ok(default),
+    }
+}
+
+/// Compiles the arguments of a function that has a fixed number of inputs.
+/// Inputs that aren't given as arguments are constants, with the value of
+/// the property with the same name or the default value.
+fn compile_inputs(args: Vec<Node<ExpressionNode>>, 
+                  const_args: &ConstArgs,
+                  defaults: &[(&str, f32)],
+                  probes: &mut Vec<(Id, f32, Node<ExpressionNode>)>, 
+                  vars: &HashMap<String, Id>, synth: &mut Synth,
+                  ctx: &Context)
+                   -> Result<Vec<Id>, CompileError> {
+    if args.len() > defaults.len() {
+        return Err(CompileError {
+            kind: CompileErrorKind::InvalidArgNumber,
+            pos: args[defaults.len()].pos
+        });
+    }
+
+    let mut inputs = Vec::with_capacity(defaults.len());
+    let mut args = args.into_iter();
+    for &(name, default) in defaults {
+        inputs.push(match args.next() {
+            Some(arg) => compile_expression(arg, probes, vars, synth, ctx)?,
+            None => {
+                let value = const_arg(const_args, name, default);
+                let node_id = synth.add_node(NodeKind::Constant(value), &[], &[]);
+                synth.get_node_output(node_id).unwrap()
+            },
+        });
+    }
+
+    Ok(inputs)
+}
+
+/// Turns the properties of a function that are beats or notes into plain numbers
+fn resolve_const_args(mut const_args: ConstArgs, ctx: &Context) -> ConstArgs {
+    let values = const_args.positional.iter_mut().chain(const_args.named.values_mut());
+    for value in values {
+        resolve_const_value(value, ctx);
+    }
+    const_args
+}
+
+fn resolve_const_value(value: &mut Node<ConstValue>, ctx: &Context) {
+    match value.kind {
+        ConstValue::Beats(beats) => value.kind = ConstValue::Float(ctx.beats_to_seconds(beats)),
+        ConstValue::Note(midi) => value.kind = ConstValue::Float(ctx.note_to_freq(midi)),
+        ConstValue::List(ref mut values) => {
+            for value in values {
+                resolve_const_value(value, ctx);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Builds a stack of fm operators. Operator ``n`` (counting from 1) modulates 
+/// operator ``algo[n]``, or goes to the output if that is 0. Operators can only
+/// modulate operators with a lower number, and only the last one has feedback.
+fn compile_fm(pos: Option<(usize, usize)>,
+              const_args: ConstArgs,
+              args: Vec<Node<ExpressionNode>>,
+              probes: &mut Vec<(Id, f32, Node<ExpressionNode>)>, 
+              vars: &HashMap<String, Id>, synth: &mut Synth,
+              ctx: &Context)
+               -> Result<Id, CompileError> {
+    let ratios = const_list(&const_args, "ratios")?;
+    let levels = const_list(&const_args, "levels")?;
+    let algo = const_list(&const_args, "algo")?;
+    let n_operators = [&ratios, &levels, &algo].iter()
+        .filter_map(|list| list.as_ref().map(|v| v.len()))
+        .max()
+        .unwrap_or(1)
+        .max(args.len().saturating_sub(1));
+
+    // By default every operator modulates the one before it
+    let algo: Vec<usize> = match algo {
+        Some(algo) => algo.into_iter().map(|v| v as usize).collect(),
+        None => (0..n_operators).collect(),
+    };
+    for (i, &target) in algo.iter().enumerate() {
+        if target > i {
+            return Err(CompileError {
+                kind: CompileErrorKind::InvalidProperty("algo".to_string()),
+                pos: const_arg_pos(&const_args, "algo")
+            });
+        }
+    }
+
+    let mut args = args.into_iter();
+    let freq = match args.next() {
+        Some(arg) => compile_expression(arg, probes, vars, synth, ctx)?,
+        None => return Err(CompileError {
+            kind: CompileErrorKind::InvalidArgNumber,
+            pos
+        }),
+    };
+    let mut level_inputs = Vec::new();
+    for arg in args {
+        level_inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+    }
+
+    let constant = |synth: &mut Synth, value: f32| {
+        let node_id = synth.add_node(NodeKind::Constant(value), &[], &[]);
+        synth.get_node_output(node_id).unwrap()
+    };
+    let feedback = const_arg(&const_args, "feedback", 0.0);
+
+    // Where the outputs of the operators go, the output is at index 0.
+    // The operators are added from the last one to the first, so that
+    // the modulators are always calculated before what they modulate.
+    let mut targets: Vec<Option<Id>> = vec![None; n_operators + 1];
+    for i in (0..n_operators).rev() {
+        let ratio = ratios.as_ref().and_then(|v| v.get(i)).copied().unwrap_or(1.0);
+        let ratio = constant(synth, ratio);
+        let node_id = synth.add_node(NodeKind::ConstantOp(Operator::Mult), &[freq, ratio], &[]);
+        let op_freq = synth.get_node_output(node_id).unwrap();
+
+        let pm = match targets[i + 1] {
+            Some(pm) => pm,
+            None => constant(synth, 0.0),
+        };
+        let op_feedback = constant(synth, if i == n_operators - 1 { feedback } else { 0.0 });
+        let node_id = synth.add_node(NodeKind::FmOperator, &[op_freq, pm, op_feedback], &[]);
+        let mut output = synth.get_node_output(node_id).unwrap();
+
+        let level = levels.as_ref().and_then(|v| v.get(i)).copied().unwrap_or(1.0);
+        let level = constant(synth, level);
+        let node_id = synth.add_node(NodeKind::ConstantOp(Operator::Mult), &[output, level], &[]);
+        output = synth.get_node_output(node_id).unwrap();
+        if let Some(&level) = level_inputs.get(i) {
+            let node_id = synth.add_node(NodeKind::ConstantOp(Operator::Mult), &[output, level], &[]);
+            output = synth.get_node_output(node_id).unwrap();
+        }
+
+        let target = algo.get(i).copied().unwrap_or(0);
+        targets[target] = Some(match targets[target] {
+            Some(prev) => {
+                let node_id = synth.add_node(NodeKind::ConstantOp(Operator::Add), &[prev, output], &[]);
+                synth.get_node_output(node_id).unwrap()
+            },
+            None => output,
+        });
+    }
+
+    Ok(targets[0].unwrap_or_else(|| constant(synth, 0.0)))
+}
+
+/// The value of an expression that is just a number
+fn literal_value(expr: &ExpressionNode, ctx: &Context) -> Option<f32> {
+    match *expr {
+        ExpressionNode::Float(value) => Some(value),
+        ExpressionNode::Beats(beats) => Some(ctx.beats_to_seconds(beats)),
+        ExpressionNode::Note(midi) => Some(ctx.note_to_freq(midi)),
+        _ => None,
+    }
+}
+
 fn compile_expression(expr: Node<ExpressionNode>, 
                       probes: &mut Vec<(Id, f32, Node<ExpressionNode>)>, 
-                      vars: &HashMap<String, Id>, synth: &mut Synth)
+                      vars: &HashMap<String, Id>, synth: &mut Synth,
+                      ctx: &Context)
                        -> Result<Id, CompileError> {
     match expr.kind {
-        ExpressionNode::Float(value) => {
+        ExpressionNode::Float(_) | ExpressionNode::Beats(_) | ExpressionNode::Note(_) => {
+            let value = literal_value(&expr.kind, ctx).unwrap();
             let node_id = synth.add_node(NodeKind::Constant(value), &[], &[]);
             Ok(synth.get_node_output(node_id).unwrap())
         },
+        ExpressionNode::String(_) => {
+            Err(CompileError {
+                kind: CompileErrorKind::UnexpectedString,
+                pos: expr.pos
+            })
+        },
         ExpressionNode::Variable(string) => {
             if let Some(&id) = vars.get(&string) {
                 Ok(id)
@@ -98,9 +514,9 @@ fn compile_expression(expr: Node<ExpressionNode>,
         ExpressionNode::Operator(op, args) => {
             if args.len() >= 2 {
                 let mut args = args.into_iter();
-                let mut prev_arg = compile_expression(args.next().unwrap(), probes, vars, synth)?;
+                let mut prev_arg = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
                 for arg in args {
-                    let current = compile_expression(arg, probes, vars, synth)?;
+                    let current = compile_expression(arg, probes, vars, synth, ctx)?;
                     let node_id = synth.add_node(NodeKind::ConstantOp(op), &[prev_arg, current], &[]);
                     prev_arg = synth.get_node_output(node_id).unwrap();
                 }
@@ -109,119 +525,113 @@ fn compile_expression(expr: Node<ExpressionNode>,
             }else{
                 Err(CompileError {
                     kind: CompileErrorKind::InvalidNumberOfOperatorArgs,
-                    pos: args.get(0).map(|v| v.pos).flatten()
+                    pos: args.first().and_then(|v| v.pos)
                 })
             }
         },
-        ExpressionNode::FunctionCall(name, const_args, args) => {
+        ExpressionNode::FunctionCall(name, const_args, args, body) => {
+            let const_args = resolve_const_args(const_args, ctx);
+            if name != "poly" && name != "oversample" && body.is_some() {
+                return Err(CompileError {
+                    kind: CompileErrorKind::UnexpectedBody,
+                    pos: expr.pos
+                });
+            }
+
             match name.as_str() {
                 "delay" => {
                     if args.len() == 2 {
                         let mut args = args.into_iter();
-                        let time = compile_expression(args.next().unwrap(), probes, vars, synth)?;
+                        let time = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
                         let expr = args.next().unwrap();
                         let probe_id = synth.allocate_probe_id();
-                        let max = if let Some(max) = const_args.get("max") { max.kind }
-                                  else { 5.0 };
+                        let max = const_arg(&const_args, "max", 5.0);
+                        let interpolation = const_interpolation(&const_args, Interpolation::None)?;
 
                         probes.push((probe_id, max, expr));
-                        let node_id = synth.add_node(NodeKind::Delay(max, probe_id), &[time], &[]);
+                        let node_id = synth.add_node(NodeKind::Delay(max, probe_id, interpolation), &[time], &[]);
                         Ok(synth.get_node_output(node_id).unwrap())
                     }else {
                         Err(CompileError {
                             kind: CompileErrorKind::InvalidArgNumber,
-                            pos: args.get(0).map(|v| v.pos).flatten()
+                            pos: args.first().and_then(|v| v.pos)
                         })
                     }
                 },
                 "clamp" => {
                     if args.len() == 1 {
                         let mut args = args.into_iter();
-                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth)?;
-                        let min = if let Some(min) = const_args.get("min") { min.kind }
-                                  else { -1.0 };
-                        let max = if let Some(max) = const_args.get("max") { max.kind }
-                                  else { 1.0 };
-                        println!("{} -> {}", min, max);
+                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
+                        let min = const_arg(&const_args, "min", -1.0);
+                        let max = const_arg(&const_args, "max", 1.0);
+                        if min > max {
+                            return Err(CompileError {
+                                kind: CompileErrorKind::InvalidProperty("min".to_string()),
+                                pos: const_arg_pos(&const_args, "min")
+                            });
+                        }
                         let node_id = synth.add_node(NodeKind::Clamp(min, max), &[arg_1], &[]);
                         Ok(synth.get_node_output(node_id).unwrap())
                     }else {
                         Err(CompileError {
                             kind: CompileErrorKind::InvalidArgNumber,
-                            pos: args.get(0).map(|v| v.pos).flatten()
+                            pos: args.first().and_then(|v| v.pos)
                         })
                     }
                 },
                 "square" => {
                     if args.len() == 1 {
                         let mut args = args.into_iter();
-                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth)?;
-                        let offset = if let Some(off) = const_args.get("off") {
-                            off.kind
-                        }else{
-                            0.0
-                        };
+                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
+                        let offset = const_arg(&const_args, "off", 0.0);
                         let node_id = synth.add_node(NodeKind::SquareOscillator, &[arg_1], &[offset]);
                         Ok(synth.get_node_output(node_id).unwrap())
                     }else{
                         Err(CompileError {
                             kind: CompileErrorKind::InvalidArgNumber,
-                            pos: args.get(0).map(|v| v.pos).flatten()
+                            pos: args.first().and_then(|v| v.pos)
                         })
                     }
                 },
                 "osc" => {
                     if args.len() == 1 {
                         let mut args = args.into_iter();
-                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth)?;
-                        let offset = if let Some(off) = const_args.get("off") {
-                            off.kind
-                        }else{
-                            0.0
-                        };
+                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
+                        let offset = const_arg(&const_args, "off", 0.0);
 
                         let node_id = synth.add_node(NodeKind::Oscillator, &[arg_1], &[offset]);
                         Ok(synth.get_node_output(node_id).unwrap())
                     }else{
                         Err(CompileError {
                             kind: CompileErrorKind::InvalidArgNumber,
-                            pos: args.get(0).map(|v| v.pos).flatten()
+                            pos: args.first().and_then(|v| v.pos)
                         })
                     }
                 },
                 "linear" => {
                     if args.len() == 1 {
                         let mut args = args.into_iter();
-                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth)?;
-                        let offset = if let Some(off) = const_args.get("off") {
-                            off.kind
-                        }else{
-                            0.0
-                        };
-
-                        let max = if let Some(max) = const_args.get("max") {
-                            max.kind
-                        }else{
-                            10000.0
-                        };
+                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
+                        let offset = const_arg(&const_args, "off", 0.0);
 
+                        let max = const_arg(&const_args, "max", 10000.0);
 
                         let node_id = synth.add_node(NodeKind::Linear(max), &[arg_1], &[offset]);
                         Ok(synth.get_node_output(node_id).unwrap())
                     }else{
                         Err(CompileError {
                             kind: CompileErrorKind::InvalidArgNumber,
-                            pos: args.get(0).map(|v| v.pos).flatten()
+                            pos: args.first().and_then(|v| v.pos)
                         })
                     }
                 },
                 "seq" => {
                     if args.len() > 1 {
                         let mut args = args.into_iter();
-                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth)?;
+                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
                         let mut sequence = Vec::new();
                         for arg in args {
-                            if let ExpressionNode::Float(val) = arg.kind {
+                            if let Some(val) = literal_value(&arg.kind, ctx) {
                                 sequence.push(val);
                             }else{
                                 return Err(CompileError {
@@ -235,14 +645,949 @@ fn compile_expression(expr: Node<ExpressionNode>,
                     }else{
                         Err(CompileError {
                             kind: CompileErrorKind::InvalidArgNumber,
-                            pos: args.get(0).map(|v| v.pos).flatten()
+                            pos: args.first().and_then(|v| v.pos)
+                        })
+                    }
+                },
+                "adsr" => {
+                    if !args.is_empty() && args.len() <= 2 {
+                        let mut inputs = Vec::new();
+                        for arg in args {
+                            inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+                        }
+                        let attack = const_arg(&const_args, "attack", 0.01);
+                        let decay = const_arg(&const_args, "decay", 0.1);
+                        let sustain = const_arg(&const_args, "sustain", 1.0);
+                        let release = const_arg(&const_args, "release", 0.1);
+                        let node_id = synth.add_node(NodeKind::Adsr(attack, decay, sustain, release), &inputs, &[]);
+                        Ok(synth.get_node_output(node_id).unwrap())
+                    }else{
+                        Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: args.first().and_then(|v| v.pos)
+                        })
+                    }
+                },
+                "mtof" | "ftom" => {
+                    if args.len() == 1 {
+                        let mut args = args.into_iter();
+                        let arg_1 = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
+                        let kind = if name == "mtof" {
+                            NodeKind::MidiToFreq(ctx.tuning.clone())
+                        }else{
+                            NodeKind::FreqToMidi(ctx.tuning.clone())
+                        };
+                        let node_id = synth.add_node(kind, &[arg_1], &[]);
+                        Ok(synth.get_node_output(node_id).unwrap())
+                    }else{
+                        Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: args.first().and_then(|v| v.pos)
+                        })
+                    }
+                },
+                "beat" | "bar" | "clock" => {
+                    if args.is_empty() {
+                        // Clocks tick "div" times per beat, and bars
+                        // are "beats" beats long
+                        let ticks_per_beat = match name.as_str() {
+                            "beat" => 1.0,
+                            "bar" => 1.0 / const_arg(&const_args, "beats", 4.0),
+                            _ => const_arg(&const_args, "div", 1.0),
+                        };
+                        let rate = ticks_per_beat * ctx.tempo / 60.0;
+                        let node_id = synth.add_node(NodeKind::Clock(rate), &[], &[]);
+                        Ok(synth.get_node_output(node_id).unwrap())
+                    }else{
+                        Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: args.first().and_then(|v| v.pos)
                         })
                     }
                 },
+                "pattern" => {
+                    if args.is_empty() || args.len() > 2 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: args.first().and_then(|v| v.pos)
+                        });
+                    }
+
+                    let mut args = args.into_iter();
+                    let pattern_arg = args.next().unwrap();
+                    let steps = match pattern_arg.kind {
+                        ExpressionNode::String(ref string) => 
+                            pattern::parse_pattern(string, |midi| ctx.note_to_freq(midi))
+                                .map_err(|step| CompileError {
+                                    kind: CompileErrorKind::InvalidPattern(step),
+                                    pos: pattern_arg.pos
+                                })?,
+                        _ => return Err(CompileError {
+                            kind: CompileErrorKind::ExpectedString,
+                            pos: pattern_arg.pos
+                        }),
+                    };
+
+                    // The steps are triggered by the second argument, or
+                    // by a clock with "div" ticks per beat if there isn't one.
+                    let trigger = match args.next() {
+                        Some(arg) => compile_expression(arg, probes, vars, synth, ctx)?,
+                        None => {
+                            let rate = const_arg(&const_args, "div", 4.0) * ctx.tempo / 60.0;
+                            let clock = synth.add_node(NodeKind::Clock(rate), &[], &[]);
+                            synth.get_node_nth_output(clock, 1).unwrap()
+                        },
+                    };
+
+                    let gate_length = const_arg(&const_args, "gate", 0.8);
+                    let seed = synth.node_seed("pattern");
+                    let node_id = synth.add_node(NodeKind::Pattern(steps, gate_length, seed), &[trigger], &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "fm_op" => {
+                    let offset = const_arg(&const_args, "off", 0.0);
+                    let inputs = compile_inputs(args, &const_args, &[("freq", 0.0), ("pm", 0.0), ("feedback", 0.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::FmOperator, &inputs, &[offset]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "fm" => compile_fm(expr.pos, const_args, args, probes, vars, synth, ctx),
+                "wavetable" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    // Every file is split into this many frames, lists are always one frame
+                    let frames_per_file = const_arg(&const_args, "frames", 1.0).max(1.0) as usize;
+                    let mut frames = Vec::new();
+                    for value in &const_args.positional {
+                        match &value.kind {
+                            ConstValue::String(file) => {
+                                let (samples, _) = ctx.read_wave(file, None, value.pos, None)?;
+                                let frame_len = samples.len() / frames_per_file;
+                                if frame_len > 0 {
+                                    frames.extend(samples.chunks_exact(frame_len).map(|v| v.to_vec()));
+                                }
+                            },
+                            ConstValue::List(values) => {
+                                let mut frame = Vec::with_capacity(values.len());
+                                for value in values {
+                                    match value.kind {
+                                        ConstValue::Float(value) => frame.push(value),
+                                        _ => return Err(CompileError {
+                                            kind: CompileErrorKind::ExpectedFloat,
+                                            pos: value.pos
+                                        }),
+                                    }
+                                }
+                                if !frame.is_empty() {
+                                    frames.push(frame);
+                                }
+                            },
+                            _ => return Err(CompileError {
+                                kind: CompileErrorKind::ExpectedString,
+                                pos: value.pos
+                            }),
+                        }
+                    }
+
+                    if frames.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::EmptyWavetable,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let interpolation = const_interpolation(&const_args, Interpolation::Linear)?;
+                    if interpolation == Interpolation::Allpass {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("interp".to_string()),
+                            pos: const_arg_pos(&const_args, "interp")
+                        });
+                    }
+                    let offset = const_arg(&const_args, "off", 0.0);
+                    let inputs = compile_inputs(args, &const_args, &[("freq", 0.0), ("position", 0.0)], probes, vars, synth, ctx)?;
+                    let kind = NodeKind::Wavetable(Rc::new(WavetableData { frames }), interpolation);
+                    let node_id = synth.add_node(kind, &inputs, &[offset]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "reverb" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, 
+                        &[("signal", 0.0), ("room", 0.5), ("damp", 0.5), ("pre_delay", 0.0), ("mix", 0.3)], 
+                        probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Reverb(Rc::new(ReverbLines::new(ctx.sample_rate))), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "chorus" | "flanger" | "phaser" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let (kind, defaults) = match name.as_str() {
+                        "chorus" => (NodeKind::Chorus, [0.8, 0.5, 0.0, 0.5]),
+                        "flanger" => (NodeKind::Flanger, [0.2, 0.7, 0.6, 0.5]),
+                        _ => {
+                            let stages = const_arg(&const_args, "stages", 4.0);
+                            if stages < 1.0 {
+                                return Err(CompileError {
+                                    kind: CompileErrorKind::InvalidProperty("stages".to_string()),
+                                    pos: const_arg_pos(&const_args, "stages")
+                                });
+                            }
+                            (NodeKind::Phaser(stages as usize), [0.5, 0.8, 0.3, 0.5])
+                        },
+                    };
+
+                    let [rate, depth, feedback, mix] = defaults;
+                    let inputs = compile_inputs(args, &const_args, 
+                        &[("signal", 0.0), ("rate", rate), ("depth", depth), ("feedback", feedback), ("mix", mix)], 
+                        probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(kind, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "compress" | "gate" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let (kind, threshold, ratio, attack, release) = match name.as_str() {
+                        "compress" => (NodeKind::Compressor, -20.0, 4.0, 0.01, 0.1),
+                        _ => (NodeKind::Gate, -50.0, 10.0, 0.001, 0.1),
+                    };
+
+                    // Without a sidechain, the signal is its own sidechain
+                    let mut args = args.into_iter();
+                    let signal = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
+                    let sidechain = match args.next() {
+                        Some(arg) => compile_expression(arg, probes, vars, synth, ctx)?,
+                        None => signal,
+                    };
+
+                    let mut inputs = vec![signal, sidechain];
+                    inputs.extend(compile_inputs(args.collect(), &const_args,
+                        &[("threshold", threshold), ("ratio", ratio), ("attack", attack), ("release", release), ("gain", 0.0)],
+                        probes, vars, synth, ctx)?);
+                    let node_id = synth.add_node(kind, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "limit" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let lookahead = const_arg(&const_args, "lookahead", 0.005).max(0.0);
+                    let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("threshold", -0.3), ("release", 0.05)], probes, vars, synth, ctx)?;
+                    // The gain starts at 1
+                    let node_id = synth.add_node(NodeKind::Limiter(lookahead), &inputs, &[0.0, 1.0]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "tanh" | "softclip" | "foldback" | "tube" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let shape = match name.as_str() {
+                        "tanh" => Shape::Tanh,
+                        "softclip" => Shape::SoftClip,
+                        "foldback" => Shape::Foldback,
+                        _ => Shape::Tube,
+                    };
+
+                    let factor = const_arg(&const_args, "oversample", 1.0);
+                    if factor < 1.0 || factor.fract() != 0.0 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("oversample".to_string()),
+                            pos: const_arg_pos(&const_args, "oversample")
+                        });
+                    }
+                    let oversampler = if factor as usize > 1 {
+                        Some(Rc::new(Oversampler::new(factor as usize)))
+                    }else{
+                        None
+                    };
+
+                    let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("drive", 1.0), ("bias", 0.3)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Shaper(shape, oversampler), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "bitcrush" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("bits", 8.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Bitcrush, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "decimate" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("rate", 8000.0)], probes, vars, synth, ctx)?;
+                    // Start with a full phase, so that the first sample is held right away
+                    let node_id = synth.add_node(NodeKind::Decimate, &inputs, &[1.0]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "sample_hold" => {
+                    if args.len() != 2 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, &[("trig", 0.0), ("input", 0.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::SampleHold, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "slew" | "lag" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let (kind, inputs) = if name == "slew" {
+                        // The fall time is the same as the rise time if it's not given
+                        let rise = const_arg(&const_args, "rise", 0.1);
+                        let fall = const_arg(&const_args, "fall", rise);
+                        let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("rise", rise), ("fall", fall)], probes, vars, synth, ctx)?;
+                        (NodeKind::Slew, inputs)
+                    }else{
+                        let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("time", 0.05)], probes, vars, synth, ctx)?;
+                        (NodeKind::Lag, inputs)
+                    };
+                    let node_id = synth.add_node(kind, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "rising_edge" | "falling_edge" => {
+                    if args.len() != 1 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let kind = if name == "rising_edge" { NodeKind::RisingEdge } else { NodeKind::FallingEdge };
+                    let input = compile_expression(args.into_iter().next().unwrap(), probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(kind, &[input], &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "counter" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let max = const_arg(&const_args, "max", 0.0).max(0.0).floor();
+                    let inputs = compile_inputs(args, &const_args, &[("trig", 0.0), ("reset", 0.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Counter(max), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "clock_div" | "euclid" => {
+                    if args.len() != 1 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let kind = if name == "clock_div" {
+                        let n = const_arg(&const_args, "n", 2.0);
+                        if n < 1.0 {
+                            return Err(CompileError {
+                                kind: CompileErrorKind::InvalidProperty("n".to_string()),
+                                pos: const_arg_pos(&const_args, "n")
+                            });
+                        }
+                        NodeKind::ClockDiv(n as u32)
+                    }else{
+                        let steps = const_arg(&const_args, "steps", 8.0);
+                        let pulses = const_arg(&const_args, "pulses", 3.0);
+                        if steps < 1.0 {
+                            return Err(CompileError {
+                                kind: CompileErrorKind::InvalidProperty("steps".to_string()),
+                                pos: const_arg_pos(&const_args, "steps")
+                            });
+                        }
+                        if pulses < 0.0 || pulses > steps {
+                            return Err(CompileError {
+                                kind: CompileErrorKind::InvalidProperty("pulses".to_string()),
+                                pos: const_arg_pos(&const_args, "pulses")
+                            });
+                        }
+                        let rotate = const_arg(&const_args, "rotate", 0.0).max(0.0);
+                        NodeKind::Euclid(trigger::euclid_pattern(steps as usize, pulses as usize, rotate as usize))
+                    };
+
+                    let input = compile_expression(args.into_iter().next().unwrap(), probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(kind, &[input], &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "pulse" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, &[("trig", 0.0), ("length", 0.01)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Pulse, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "random" | "random_walk" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let min = const_arg(&const_args, "min", if name == "random" { 0.0 } else { -1.0 });
+                    let max = const_arg(&const_args, "max", 1.0);
+                    if min > max {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("min".to_string()),
+                            pos: const_arg_pos(&const_args, "min")
+                        });
+                    }
+
+                    let seed = synth.node_seed(&name);
+                    let (kind, inputs, start) = if name == "random" {
+                        let inputs = compile_inputs(args, &const_args, &[("trig", 0.0)], probes, vars, synth, ctx)?;
+                        (NodeKind::Random(seed, min, max), inputs, min)
+                    }else{
+                        let inputs = compile_inputs(args, &const_args, &[("trig", 0.0), ("step", 0.1)], probes, vars, synth, ctx)?;
+                        (NodeKind::RandomWalk(seed, min, max), inputs, (min + max) / 2.0)
+                    };
+                    let node_id = synth.add_node(kind, &inputs, &[0.0, 0.0, start]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "chance" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let seed = synth.node_seed("chance");
+                    let inputs = compile_inputs(args, &const_args, &[("trig", 0.0), ("p", 0.5)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Chance(seed), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "choose" => {
+                    if args.len() < 2 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let mut inputs = Vec::with_capacity(args.len());
+                    for arg in args {
+                        inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+                    }
+                    let seed = synth.node_seed("choose");
+                    let node_id = synth.add_node(NodeKind::Choose(seed, inputs.len() - 1), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "pluck" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let seed = synth.node_seed("pluck");
+                    let inputs = compile_inputs(args, &const_args, 
+                        &[("trig", 0.0), ("freq", 220.0), ("decay", 2.0), ("damping", 0.5), ("brightness", 0.7)], 
+                        probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Pluck(seed), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "modal" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let freqs = match const_list(&const_args, "freqs")? {
+                        Some(freqs) if !freqs.is_empty() => freqs,
+                        _ => return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("freqs".to_string()),
+                            pos: const_arg_pos(&const_args, "freqs").or(expr.pos)
+                        }),
+                    };
+                    let decays = const_list(&const_args, "decays")?.unwrap_or_default();
+                    let gains = const_list(&const_args, "gains")?.unwrap_or_default();
+
+                    // Modes without a decay or gain use the last one given
+                    let modes = freqs.iter().enumerate().map(|(i, &ratio)| physical::Mode {
+                        ratio,
+                        decay: decays.get(i).or(decays.last()).copied().unwrap_or(1.0),
+                        gain: gains.get(i).or(gains.last()).copied().unwrap_or(1.0),
+                    }).collect();
+
+                    let inputs = compile_inputs(args, &const_args, &[("input", 0.0), ("pitch", 1.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Modal(modes), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "env_follow" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let rms = match const_arg(&const_args, "mode", 0.0) as u32 {
+                        0 => false,
+                        1 => true,
+                        _ => return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("mode".to_string()),
+                            pos: const_arg_pos(&const_args, "mode")
+                        }),
+                    };
+
+                    let inputs = compile_inputs(args, &const_args, 
+                        &[("signal", 0.0), ("attack", 0.01), ("release", 0.1)], 
+                        probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::EnvFollow(rms), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "pitch_track" => {
+                    if args.len() != 1 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let tracker = analysis::PitchTracker {
+                        min_freq: const_arg(&const_args, "min", 50.0),
+                        max_freq: const_arg(&const_args, "max", 1000.0),
+                        threshold: const_arg(&const_args, "threshold", 0.15),
+                    };
+                    if tracker.min_freq < 1.0 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("min".to_string()),
+                            pos: const_arg_pos(&const_args, "min")
+                        });
+                    }
+                    if tracker.max_freq <= tracker.min_freq {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("max".to_string()),
+                            pos: const_arg_pos(&const_args, "max")
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, &[("signal", 0.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::PitchTrack(tracker), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "input" => {
+                    if !args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let name = const_string(&const_args, 0, expr.pos)?;
+                    let channel = const_args.named.get("channel").map(|_| const_arg(&const_args, "channel", 0.0) as usize);
+
+                    // Inputs given when running the synth are used instead of the file
+                    let path = match ctx.inputs.get(name.kind) {
+                        Some(path) => path.clone(),
+                        None => ctx.directory.join(name.kind),
+                    };
+                    ctx.open_wave(&path, channel, name.pos, const_arg_pos(&const_args, "channel"))?;
+
+                    let node_id = synth.add_node(NodeKind::Input(InputFile { path, channel }), &[], &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "spectral_freeze" | "spectral_gate" | "pitch_shift" | "vocoder" => {
+                    let (effect, defaults): (_, &[(&str, f32)]) = match name.as_str() {
+                        "spectral_freeze" => (spectral::Effect::Freeze, &[("signal", 0.0), ("freeze", 0.0)]),
+                        "spectral_gate" => (spectral::Effect::Gate, &[("signal", 0.0), ("threshold", -40.0)]),
+                        "pitch_shift" => (spectral::Effect::PitchShift, &[("signal", 0.0), ("semitones", 0.0)]),
+                        _ => (spectral::Effect::Vocoder, &[("carrier", 0.0), ("modulator", 0.0)]),
+                    };
+                    if args.is_empty() || (effect == spectral::Effect::Vocoder && args.len() != 2) {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let size = const_arg(&const_args, "size", 1024.0) as usize;
+                    if !size.is_power_of_two() || size < 64 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("size".to_string()),
+                            pos: const_arg_pos(&const_args, "size")
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, defaults, probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Spectral(effect, size), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "pan" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("position", 0.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Pan, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "width" => {
+                    if args.len() < 2 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let inputs = compile_inputs(args, &const_args, 
+                        &[("left", 0.0), ("right", 0.0), ("width", 1.0)], 
+                        probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Width, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "mix" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    // Inputs without a gain are left as they are, and inputs without
+                    // a position are in the middle
+                    let gains = const_list(&const_args, "gains")?.unwrap_or_default();
+                    let pans = const_list(&const_args, "pans")?;
+                    if gains.len() > args.len() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("gains".to_string()),
+                            pos: const_arg_pos(&const_args, "gains")
+                        });
+                    }
+                    if pans.as_ref().is_some_and(|pans| pans.len() > args.len()) {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("pans".to_string()),
+                            pos: const_arg_pos(&const_args, "pans")
+                        });
+                    }
+
+                    let mut inputs = Vec::with_capacity(args.len());
+                    for arg in args {
+                        inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+                    }
+
+                    let gain = |i: usize| gains.get(i).copied().unwrap_or(1.0);
+                    let kind = match pans {
+                        Some(pans) => NodeKind::StereoMix(
+                            (0..inputs.len()).map(|i| (gain(i), pans.get(i).copied().unwrap_or(0.0))).collect()
+                        ),
+                        None => NodeKind::Mix((0..inputs.len()).map(gain).collect()),
+                    };
+                    let node_id = synth.add_node(kind, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "select" | "switch" => {
+                    if (name == "select" && args.len() != 3) || args.len() < 2 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let mut args = args.into_iter();
+                    let mut index = compile_expression(args.next().unwrap(), probes, vars, synth, ctx)?;
+                    if name == "select" {
+                        // The first branch is chosen when the condition is true
+                        let node_id = synth.add_node(NodeKind::Function(Function::Not), &[index], &[]);
+                        index = synth.get_node_output(node_id).unwrap();
+                    }
+
+                    let fade = const_arg(&const_args, "fade", 0.0);
+                    let branch_id = synth.add_node(NodeKind::Branch(Vec::new(), fade), &[index], &branch::INITIAL_DATA);
+
+                    // The nodes of every branch are added right after each other,
+                    // so that the ones that aren't chosen can be skipped
+                    let mut ranges = Vec::new();
+                    let mut inputs = Vec::new();
+                    for i in 0..branch::OUTPUT_STATE_INPUTS {
+                        inputs.push(synth.get_node_data_loc(branch_id, i).unwrap());
+                    }
+                    for arg in args {
+                        let start = synth.n_nodes();
+                        inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+                        ranges.push((start, synth.n_nodes()));
+                    }
+
+                    let n_branches = ranges.len();
+                    synth.set_node_kind(branch_id, NodeKind::Branch(ranges, fade));
+                    let node_id = synth.add_node(NodeKind::BranchOutput(n_branches), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "convolve" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let block_size = const_arg(&const_args, "block", 256.0);
+                    if block_size < 1.0 || !(block_size as usize).is_power_of_two() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("block".to_string()),
+                            pos: const_arg_pos(&const_args, "block")
+                        });
+                    }
+
+                    let file = const_string(&const_args, 0, expr.pos)?;
+                    let channel = const_args.named.get("channel").map(|_| const_arg(&const_args, "channel", 0.0) as usize);
+                    let (mut impulse, impulse_rate) = ctx.read_wave(file.kind, channel, file.pos, const_arg_pos(&const_args, "channel"))?;
+                    if impulse.is_empty() {
+                        impulse.push(0.0);
+                    }
+
+                    let conv = ConvolutionData::new(&impulse, impulse_rate as f32, ctx.sample_rate, block_size as usize);
+                    let inputs = compile_inputs(args, &const_args, &[("signal", 0.0), ("mix", 1.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(NodeKind::Convolve(Rc::new(conv)), &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "sample" => {
+                    if args.is_empty() {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: expr.pos
+                        });
+                    }
+
+                    let file = const_string(&const_args, 0, expr.pos)?;
+                    let channel = const_args.named.get("channel").map(|_| const_arg(&const_args, "channel", 0.0) as usize);
+                    let (samples, sample_rate) = ctx.read_wave(file.kind, channel, file.pos, const_arg_pos(&const_args, "channel"))?;
+
+                    // Times are given in seconds, but the node wants them in samples
+                    let sample_rate = sample_rate as f32;
+                    let length = samples.len() as f32 / sample_rate;
+                    let start = const_args.named.get("start").map(|_| const_arg(&const_args, "start", 0.0).clamp(0.0, length) * sample_rate);
+                    let loop_start = const_arg(&const_args, "loop_start", 0.0).clamp(0.0, length) * sample_rate;
+                    let loop_end = const_arg(&const_args, "loop_end", length).clamp(0.0, length) * sample_rate;
+                    let looping = const_arg(&const_args, "loop", 0.0) > 0.0 && loop_end > loop_start;
+
+                    let data = SampleData { samples, sample_rate };
+                    let kind = NodeKind::Sample(Rc::new(data), start, if looping { Some((loop_start, loop_end)) } else { None });
+                    let inputs = compile_inputs(args, &const_args, &[("trig", 0.0), ("rate", 1.0)], probes, vars, synth, ctx)?;
+                    let node_id = synth.add_node(kind, &inputs, &[]);
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "oversample" => {
+                    let body = match body {
+                        Some(body) => body,
+                        None => return Err(CompileError {
+                            kind: CompileErrorKind::ExpectedBody,
+                            pos: expr.pos
+                        }),
+                    };
+
+                    let factor = const_arg(&const_args, "factor", 4.0);
+                    if factor < 1.0 || factor.fract() != 0.0 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("factor".to_string()),
+                            pos: const_arg_pos(&const_args, "factor")
+                        });
+                    }
+                    let factor = factor as usize;
+                    let oversampler = Rc::new(Oversampler::new(factor));
+
+                    let mut inputs = Vec::with_capacity(args.len());
+                    for arg in args {
+                        inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+                    }
+
+                    // The kind is set when we know where the oversampled nodes end
+                    let start = synth.add_node(NodeKind::Oversample(0, factor), &[], &[]);
+
+                    // The arguments are upsampled, and are called in1, in2 and so
+                    // on in the body, and the first one is also called in.
+                    // The body has to set its own out.
+                    let mut scope = vars.clone();
+                    scope.remove("out");
+                    for (i, &input) in inputs.iter().enumerate() {
+                        let node_id = synth.add_node(NodeKind::Upsample(oversampler.clone()), &[input], &[]);
+                        let upsampled = synth.get_node_output(node_id).unwrap();
+                        if i == 0 {
+                            scope.insert("in".to_string(), upsampled);
+                        }
+                        scope.insert(format!("in{}", i + 1), upsampled);
+                    }
+
+                    let body_ctx = Context { sample_rate: ctx.sample_rate * factor as f32, ..ctx.clone() };
+                    let body_vars = compile_scope(body, scope, synth, &body_ctx)?;
+                    let output = match body_vars.get("out") {
+                        Some(&output) => output,
+                        None => return Err(CompileError {
+                            kind: CompileErrorKind::NoOutputVariables,
+                            pos: expr.pos
+                        }),
+                    };
+
+                    let node_id = synth.add_node(NodeKind::Downsample(oversampler), &[output], &[]);
+                    synth.set_node_kind(start, NodeKind::Oversample(synth.n_nodes(), factor));
+                    Ok(synth.get_node_output(node_id).unwrap())
+                },
+                "poly" => {
+                    let body = match body {
+                        Some(body) => body,
+                        None => return Err(CompileError {
+                            kind: CompileErrorKind::ExpectedBody,
+                            pos: expr.pos
+                        }),
+                    };
+
+                    // The arguments are (gate, freq, velocity) for every stream
+                    // of notes, but if there is only one stream the velocity
+                    // can be left out.
+                    if args.len() != 2 && (args.is_empty() || args.len() % poly::STREAM_INPUTS != 0) {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidArgNumber,
+                            pos: args.first().and_then(|v| v.pos)
+                        });
+                    }
+
+                    let voices = const_arg(&const_args, "voices", 8.0);
+                    if voices < 1.0 || voices.fract() != 0.0 {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("voices".to_string()),
+                            pos: const_arg_pos(&const_args, "voices")
+                        });
+                    }
+                    let voices = voices as usize;
+
+                    let steal = match const_arg(&const_args, "steal", 0.0) as u32 {
+                        0 => VoiceSteal::Oldest,
+                        1 => VoiceSteal::Quietest,
+                        _ => return Err(CompileError {
+                            kind: CompileErrorKind::InvalidProperty("steal".to_string()),
+                            pos: const_arg_pos(&const_args, "steal")
+                        }),
+                    };
+
+                    let mut inputs = Vec::new();
+                    for arg in args {
+                        inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+                    }
+                    if inputs.len() == 2 {
+                        let velocity = synth.add_node(NodeKind::Constant(1.0), &[], &[]);
+                        inputs.push(synth.get_node_output(velocity).unwrap());
+                    }
+                    let streams = inputs.len() / poly::STREAM_INPUTS;
+
+                    let allocator = synth.add_node(
+                        NodeKind::VoiceAllocator(voices, streams, steal),
+                        &inputs,
+                        &poly::initial_data(voices, streams));
+
+                    // Every voice is a copy of the body, which can use
+                    // the variables of the note it is playing
+                    let mut sum = None;
+                    for voice in 0..voices {
+                        // The voice has to set its own out
+                        let mut scope = vars.clone();
+                        scope.remove("out");
+                        for &(name, parameter) in &[("freq", poly::VOICE_FREQ),
+                                                    ("gate", poly::VOICE_GATE),
+                                                    ("velocity", poly::VOICE_VELOCITY),
+                                                    ("trig", poly::VOICE_TRIGGER)] {
+                            let loc = poly::voice_data_loc(streams, voice, parameter);
+                            scope.insert(name.to_string(), synth.get_node_data_loc(allocator, loc).unwrap());
+                        }
+
+                        let voice_vars = compile_scope(body.clone(), scope, synth, ctx)?;
+                        let output = match voice_vars.get("out") {
+                            Some(&output) => output,
+                            None => return Err(CompileError {
+                                kind: CompileErrorKind::NoOutputVariables,
+                                pos: expr.pos
+                            }),
+                        };
+                        synth.set_node_input(allocator, streams * poly::STREAM_INPUTS + voice, output);
+
+                        sum = Some(match sum {
+                            Some(prev) => {
+                                let node_id = synth.add_node(NodeKind::ConstantOp(Operator::Add), &[prev, output], &[]);
+                                synth.get_node_output(node_id).unwrap()
+                            },
+                            None => output,
+                        });
+                    }
+
+                    Ok(sum.unwrap())
+                },
                 _ => {
+                    if let Some(function) = Function::from_name(&name) {
+                        if args.len() != function.n_args() {
+                            return Err(CompileError {
+                                kind: CompileErrorKind::InvalidArgNumber,
+                                pos: expr.pos
+                            });
+                        }
+
+                        let mut inputs = Vec::with_capacity(args.len());
+                        for arg in args {
+                            inputs.push(compile_expression(arg, probes, vars, synth, ctx)?);
+                        }
+                        let node_id = synth.add_node(NodeKind::Function(function), &inputs, &[]);
+                        return Ok(synth.get_node_output(node_id).unwrap());
+                    }
+
                     Err(CompileError {
                         kind: CompileErrorKind::UnknownFunctionName(name),
-                        pos: args.get(0).map(|v| v.pos).flatten()
+                        pos: args.first().and_then(|v| v.pos)
                     })
                 }
             }
@@ -1,10 +1,14 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use std::fmt;
 use crate::synth::Synth;
 use crate::synth::Id;
 
 mod tokenize;
 mod parser;
 mod compile;
+mod note;
+mod pattern;
 
 #[derive(Debug)]
 pub struct CompileError {
@@ -12,16 +16,35 @@ pub struct CompileError {
     pub pos: Option<(usize, usize)>,
 }
 
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pos {
+            Some((line, column)) => write!(f, "Error at {}:{}: {:?}", line + 1, column + 1, self.kind),
+            None => write!(f, "Error: {:?}", self.kind),
+        }
+    }
+}
+
+// The contents of the error kinds are only
+// read when they are debug printed
+#[allow(dead_code)]
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum CompileErrorKind {
     IOError(std::io::Error),
     TokenizerError(tokenize::TokenErrorKind),
     ParseError(parser::ParseErrorKind),
     CompileError(compile::CompileErrorKind),
-    TestError,
 }
 
-pub fn compile_file(path: impl AsRef<Path>) -> Result<(Synth, Id, Id), CompileError> {
+/// Compiles a patch file to run at a sample rate. If there is a seed, it's used instead
+/// of the one in the file, and the inputs are files to use for the ``input``s with those names.
+pub fn compile_file(path: impl AsRef<Path>,
+                    sample_rate: f32,
+                    seed: Option<u32>,
+                    inputs: &HashMap<String, PathBuf>) 
+        -> Result<(Synth, Id, Id), CompileError> {
+    let path = path.as_ref();
     let contents = 
         std::fs::read_to_string(path)
         .map_err(|v| CompileError { kind: CompileErrorKind::IOError(v), pos: None })?;    
@@ -32,7 +55,9 @@ pub fn compile_file(path: impl AsRef<Path>) -> Result<(Synth, Id, Id), CompileEr
     let commands = parser::parse_tokens(&mut tokens.into_iter().peekable())
         .map_err(|v| CompileError { kind: CompileErrorKind::ParseError(v.kind), pos: v.pos })?;
 
-    let (synth, left, right) = compile::compile(commands)
+    // Files used by the synth are relative to the file
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let (synth, left, right) = compile::compile(commands, directory, sample_rate, seed, inputs)
         .map_err(|v| CompileError { kind: CompileErrorKind::CompileError(v.kind), pos: v.pos })?;
 
     Ok((synth, left, right))
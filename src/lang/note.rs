@@ -0,0 +1,64 @@
+// Musical notes. Notes are kept as midi numbers for as long
+// as possible, and are turned into frequencies by the compiler,
+// using the tuning of the file.
+
+/// Reads a note, which is either a note name or a midi number
+/// like ``m60``, optionally followed by an offset in cents,
+/// like ``A4+50c`` or ``m60-12.5c``. Gives the midi number
+/// of the note, with cents as fractions of it.
+pub fn parse_note(note: &str) -> Option<f32> {
+    let (name, cents) = match note.find(['+', '-']) {
+        Some(split) if note.ends_with('c') && split > 0 => {
+            let cents: f32 = note[split..note.len() - 1].parse().ok()?;
+            (&note[..split], cents)
+        },
+        _ => (note, 0.0),
+    };
+
+    let midi = if let Some(number) = name.strip_prefix('m') {
+        if !number.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        number.parse().ok()?
+    }else{
+        parse_note_name(name)?
+    };
+
+    Some(midi + cents / 100.0)
+}
+
+/// Reads a note name like ``C4``, ``C#3`` or ``Eb5`` and
+/// gives the midi number of it, so ``A4`` is 69.
+pub fn parse_note_name(name: &str) -> Option<f32> {
+    let mut chars = name.chars().peekable();
+    let note = match chars.next()? {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let accidental = match chars.peek() {
+        Some('#') => { chars.next(); 1 },
+        Some('b') => { chars.next(); -1 },
+        _ => 0,
+    };
+
+    let octave: String = chars.collect();
+    if octave.is_empty() {
+        return None;
+    }
+    let octave: i32 = octave.parse().ok()?;
+
+    Some(((octave + 1) * 12 + note + accidental) as f32)
+}
+
+/// Whether a note name could start with this identifier, i.e.
+/// if a '#' after it is a sharp and not a comment.
+pub fn is_note_letter(identifier: &str) -> bool {
+    matches!(identifier, "A" | "B" | "C" | "D" | "E" | "F" | "G")
+}
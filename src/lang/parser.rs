@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use crate::operator::Operator;
 use crate::lang::tokenize::{ Token, TokenKind, BlockKind };
+use crate::lang::note;
 use std::iter::Peekable;
 
 #[derive(Debug)]
@@ -14,13 +15,13 @@ pub enum ParseErrorKind {
     UnexpectedEndOfFile,
     UnexpectedToken,
     ExpectedFloat,
-    ExpectedArgList,
     ExpectedCommandTerminator,
     ExpectedAssignment,
     ExpectedSeparator,
     ExpectedIdentifier,
 }
 
+#[derive(Clone)]
 pub struct Node<T> {
     pub kind: T,
     pub pos: Option<(usize, usize)>,
@@ -29,14 +30,14 @@ pub struct Node<T> {
 impl<T> Node<T> {
     pub fn new(kind: T) -> Node<T> {
         Node {
-            kind: kind,
+            kind,
             pos: None
         }
     }
 
     pub fn with_pos(kind: T, pos: (usize, usize)) -> Node<T> {
         Node {
-            kind: kind,
+            kind,
             pos: Some(pos)
         }
     }
@@ -48,18 +49,43 @@ impl<T: std::fmt::Debug> std::fmt::Debug for Node<T> {
     }
 }
 
-#[derive(Debug)]
+/// The value of a property of a function
+#[derive(Debug, Clone)]
+pub enum ConstValue {
+    Float(f32),
+    Beats(f32),
+    Note(f32),
+    String(String),
+    List(Vec<Node<ConstValue>>),
+}
+
+/// The properties of a function, i.e. what is in the brackets
+/// in ``sample["kick.wav", loop: 1]``. They can be given in order
+/// or by name.
+#[derive(Debug, Clone, Default)]
+pub struct ConstArgs {
+    pub positional: Vec<Node<ConstValue>>,
+    pub named: HashMap<String, Node<ConstValue>>,
+}
+
+#[derive(Debug, Clone)]
 pub enum ExpressionNode {
     Float(f32),
+    Beats(f32),
+    // A midi number
+    Note(f32),
+    String(String),
     Variable(String),
     Operator(Operator, Vec<Node<ExpressionNode>>),
+    // Some functions, like "poly", take a body of commands
+    // that is compiled as a sub graph of the synth.
     FunctionCall(String, 
-                 HashMap<String, 
-                 Node<f32>>, 
-                 Vec<Node<ExpressionNode>>),
+                 ConstArgs, 
+                 Vec<Node<ExpressionNode>>,
+                 Option<Vec<Node<CommandNode>>>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CommandNode {
     Assignment(String, Box<Node<ExpressionNode>>),
 }
@@ -68,7 +94,7 @@ pub fn parse_tokens(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Resul
     let mut tokens = tokens.peekable();
 
     let mut commands = Vec::new();
-    while let Some(_) = tokens.peek() {
+    while tokens.peek().is_some() {
         commands.push(parse_command(&mut tokens)?);
     }
 
@@ -94,12 +120,12 @@ fn parse_command(tokens: &mut impl Iterator<Item = Token>)
     if let Some(token) = tokens.next() {
         match token.kind {
             TokenKind::Identifier(identifier) => {
-                parse_command__ident(&mut tokens, token.pos.clone(), identifier)
+                parse_command__ident(&mut tokens, token.pos, identifier)
             },
             _ => {
                 Err(ParseError {
                     kind: ParseErrorKind::UnexpectedToken,
-                    pos: Some(token.pos.clone())
+                    pos: Some(token.pos)
                 })
             }
         }
@@ -111,6 +137,7 @@ fn parse_command(tokens: &mut impl Iterator<Item = Token>)
     }
 }
 
+#[allow(non_snake_case)]
 fn parse_command__ident(tokens: &mut Peekable<impl Iterator<Item = Token>>,
                         pos: (usize, usize), ident: String)
         -> Result<Node<CommandNode>, ParseError> {
@@ -168,6 +195,18 @@ fn parse_expression(tokens: &mut Peekable<impl Iterator<Item = Token>>)
                         token.pos
                     ))
             },
+            TokenKind::Beats(value) => {
+                Ok(Node::with_pos(
+                        ExpressionNode::Beats(value),
+                        token.pos
+                    ))
+            },
+            TokenKind::String(string) => {
+                Ok(Node::with_pos(
+                        ExpressionNode::String(string),
+                        token.pos
+                    ))
+            },
             TokenKind::Variable => {
                 // We should have an identifier after this
                 match tokens.next() {
@@ -206,7 +245,18 @@ fn parse_expression(tokens: &mut Peekable<impl Iterator<Item = Token>>)
                 )
             },
             TokenKind::Identifier(name) => {
-                Ok(parse_function(tokens, name)?)
+                // Names are only notes where a value is expected, and
+                // not when they are called like functions
+                let called = matches!(tokens.peek(), Some(Token { 
+                    kind: TokenKind::Block(BlockKind::Parenthesis | BlockKind::Bracket, _), .. 
+                }));
+                match note::parse_note(&name) {
+                    Some(midi) if !called => Ok(Node::with_pos(
+                        ExpressionNode::Note(midi),
+                        token.pos
+                    )),
+                    _ => Ok(parse_function(tokens, name)?),
+                }
             },
             _ => {
                 println!("hi");
@@ -241,27 +291,61 @@ fn parse_function(tokens: &mut Peekable<impl Iterator<Item = Token>>, name: Stri
             let const_args = if let Some(Token{kind:TokenKind::Block(_,const_args),..}) = tokens.next() { 
                 parse_const_args_list(&mut const_args.into_iter().peekable())?
             }else{ panic!("hi :=)"); };
-            let expressions = parse_args_list(tokens)?;
+            let expressions = parse_function_args(tokens)?;
+            let body = parse_body(tokens)?;
 
             Ok(Node::with_pos(
                 ExpressionNode::FunctionCall(name, 
                                              const_args, 
-                                             expressions),
+                                             expressions,
+                                             body),
                 pos
             ))
         },
         _ => {
-            let expressions = parse_args_list(tokens)?;
+            let expressions = parse_function_args(tokens)?;
+            let body = parse_body(tokens)?;
 
             Ok(Node::new(
                 ExpressionNode::FunctionCall(name, 
-                                HashMap::new(), 
-                                expressions)
+                                ConstArgs::default(), 
+                                expressions,
+                                body)
             ))
         },
     }
 }
 
+// A function that goes straight into its body,
+// i.e. ``oversample { ... }``, or that is followed by
+// the end of something, like ``beat;``, has no arguments
+fn parse_function_args(tokens: &mut Peekable<impl Iterator<Item = Token>>)
+        -> Result<Vec<Node<ExpressionNode>>, ParseError> {
+    match tokens.peek() {
+        Some(Token { kind: TokenKind::Block(BlockKind::Brace, _), .. }) |
+        Some(Token { kind: TokenKind::CommandTerminator, .. }) |
+        Some(Token { kind: TokenKind::Separator(_), .. }) |
+        None => Ok(Vec::new()),
+        _ => parse_args_list(tokens),
+    }
+}
+
+fn parse_body(tokens: &mut Peekable<impl Iterator<Item = Token>>)
+        -> Result<Option<Vec<Node<CommandNode>>>, ParseError> {
+    match tokens.peek() {
+        Some(Token { kind: TokenKind::Block(BlockKind::Brace, _), .. }) => {
+            if let Some(Token { 
+                    kind: TokenKind::Block(BlockKind::Brace, contents), 
+                    .. }) = tokens.next() {
+                Ok(Some(parse_tokens(&mut contents.into_iter().peekable())?))
+            }else{
+                panic!("Something isn't right here, the match and the if gave different results....");
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
 fn parse_args_list(tokens: &mut Peekable<impl Iterator<Item = Token>>)
         -> Result<Vec<Node<ExpressionNode>>, ParseError> {
     match tokens.peek() {
@@ -274,75 +358,109 @@ fn parse_args_list(tokens: &mut Peekable<impl Iterator<Item = Token>>)
                 panic!("Something isn't right here, the match and the if gave different results....");
             }
         },
-        c => {
+        _ => {
             Ok(vec![parse_expression(tokens)?])
         }
     }
 }
 
+/// Parses the value of a property, which is a literal or a list of literals
+/// in brackets, like ``[1, 0.5, A4]``
+fn parse_const_value(token: Token) -> Result<Node<ConstValue>, ParseError> {
+    let value = match token.kind {
+        TokenKind::Float(value) => ConstValue::Float(value),
+        TokenKind::Beats(value) => ConstValue::Beats(value),
+        TokenKind::Identifier(name) => match note::parse_note(&name) {
+            Some(midi) => ConstValue::Note(midi),
+            None => return Err(ParseError {
+                kind: ParseErrorKind::ExpectedFloat,
+                pos: Some(token.pos),
+            }),
+        },
+        TokenKind::String(string) => ConstValue::String(string),
+        TokenKind::Block(BlockKind::Bracket, contents) => {
+            let mut values = Vec::new();
+            let mut tokens = contents.into_iter();
+            while let Some(token) = tokens.next() {
+                values.push(parse_const_value(token)?);
+                match tokens.next() {
+                    Some(Token { kind: TokenKind::Separator(','), .. }) | None => (),
+                    Some(Token { pos, .. }) => return Err(ParseError {
+                        kind: ParseErrorKind::ExpectedSeparator,
+                        pos: Some(pos),
+                    }),
+                }
+            }
+            ConstValue::List(values)
+        },
+        _ => return Err(ParseError {
+            kind: ParseErrorKind::ExpectedFloat,
+            pos: Some(token.pos),
+        }),
+    };
+
+    Ok(Node::with_pos(value, token.pos))
+}
+
 fn parse_const_args_list(tokens: &mut Peekable<impl Iterator<Item = Token>>)
-        -> Result<HashMap<String, Node<f32>>, ParseError> {
+        -> Result<ConstArgs, ParseError> {
+    let mut const_args = ConstArgs::default();
     if tokens.peek().is_none() {
-        return Ok(HashMap::new());
+        return Ok(const_args);
     }
 
-    let mut map = HashMap::new();
     loop {
-        let name = match tokens.next() {
-            Some(Token { kind: TokenKind::Identifier(name), .. }) => name,
-            Some(Token { pos, .. }) => return Err(ParseError {
-                    kind: ParseErrorKind::ExpectedIdentifier,
-                    pos: Some(pos),
-            }),
-            _ => return Err(ParseError {
-                kind: ParseErrorKind::UnexpectedEndOfFile,
-                pos: None
-            }),
-        };
+        // Properties are either a name and a value, or just a value
+        let name = match tokens.peek() {
+            Some(Token { kind: TokenKind::Identifier(_), .. }) => {
+                let name = match tokens.next() {
+                    Some(Token { kind: TokenKind::Identifier(name), .. }) => name,
+                    _ => unreachable!(),
+                };
 
-        match tokens.next() {
-            Some(Token { kind: TokenKind::Assignment, .. }) => (),
-            Some(Token { pos, .. }) => return Err(ParseError {
-                    kind: ParseErrorKind::ExpectedAssignment,
-                    pos: Some(pos),
-            }),
-            _ => return Err(ParseError {
-                kind: ParseErrorKind::UnexpectedEndOfFile,
-                pos: None,
-            }),
-        }
+                match tokens.next() {
+                    Some(Token { kind: TokenKind::Assignment, .. }) => (),
+                    Some(Token { pos, .. }) => return Err(ParseError {
+                            kind: ParseErrorKind::ExpectedAssignment,
+                            pos: Some(pos),
+                    }),
+                    _ => return Err(ParseError {
+                        kind: ParseErrorKind::UnexpectedEndOfFile,
+                        pos: None,
+                    }),
+                }
 
-        let (pos, value) = match tokens.next() {
-            Some(Token { kind: TokenKind::Float(value), pos }) => (pos, value),
-            Some(Token { pos, .. }) => return Err(ParseError {
-                kind: ParseErrorKind::ExpectedFloat,
-                pos: Some(pos),
-            }),
-            _ => return Err(ParseError {
+                Some(name)
+            },
+            _ => None,
+        };
+
+        let value = match tokens.next() {
+            Some(token) => parse_const_value(token)?,
+            None => return Err(ParseError {
                 kind: ParseErrorKind::UnexpectedEndOfFile,
                 pos: None,
             }),
         };
         
-        map.insert(name, Node::with_pos(value, pos));
+        match name {
+            Some(name) => { const_args.named.insert(name, value); },
+            None => const_args.positional.push(value),
+        }
 
         match tokens.next() {
             Some(Token { kind: TokenKind::Separator(','), .. }) => {
                 continue;
             },
-            _ => break,
+            Some(token) => return Err(ParseError {
+                kind: ParseErrorKind::ExpectedSeparator,
+                pos: Some(token.pos)
+            }),
+            None => break,
         }
     }
 
-    if let Some(token) = tokens.next() {
-        println!("{:?}", &token);
-        Err(ParseError {
-            kind: ParseErrorKind::ExpectedSeparator,
-            pos: Some(token.pos)
-        })
-    }else{
-        Ok(map)
-    }
+    Ok(const_args)
 }
 
 fn parse_expression_list(tokens: &mut Peekable<impl Iterator<Item = Token>>)
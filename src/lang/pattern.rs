@@ -0,0 +1,65 @@
+// Parses patterns, i.e. the strings given to the "pattern" function.
+//
+// A pattern is a list of steps separated by whitespace:
+// * A note like ``C4``, ``Eb3`` or ``m60`` plays that note.
+// * ``x`` plays a note without changing the frequency, for drums.
+// * ``.`` is a rest.
+// * ``~`` ties the step before it, making it longer.
+// * ``|`` doesn't do anything, but can be used to mark bars.
+// Notes can have a velocity, like ``C4@0.5``, and a probability
+// of being played, like ``C4?0.25``.
+
+use crate::synth::pattern::{ Step, StepKind };
+use crate::lang::note;
+
+/// If the pattern is invalid, the step that was wrong is returned.
+pub fn parse_pattern(pattern: &str, note_to_freq: impl Fn(f32) -> f32) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for step in pattern.split_whitespace() {
+        if step == "|" {
+            continue;
+        }
+
+        steps.push(parse_step(step, &note_to_freq).ok_or_else(|| step.to_string())?);
+    }
+
+    if steps.is_empty() {
+        return Err(pattern.to_string());
+    }
+
+    Ok(steps)
+}
+
+fn parse_step(step: &str, note_to_freq: impl Fn(f32) -> f32) -> Option<Step> {
+    let end = step.find(['@', '?']).unwrap_or(step.len());
+    let (name, mut modifiers) = step.split_at(end);
+
+    let kind = match name {
+        "." => StepKind::Rest,
+        "~" => StepKind::Tie,
+        "x" => StepKind::Hit,
+        _ => StepKind::Note(note_to_freq(note::parse_note(name)?)),
+    };
+
+    let mut velocity = 1.0;
+    let mut probability = 1.0;
+    while !modifiers.is_empty() {
+        // Rests and ties don't play anything, so
+        // they can't have velocities or probabilities
+        if kind == StepKind::Rest || kind == StepKind::Tie {
+            return None;
+        }
+
+        let end = modifiers[1..].find(['@', '?']).map(|v| v + 1).unwrap_or(modifiers.len());
+        let (modifier, rest) = modifiers.split_at(end);
+        let value: f32 = modifier[1..].parse().ok()?;
+        if modifier.starts_with('@') {
+            velocity = value;
+        }else{
+            probability = value;
+        }
+        modifiers = rest;
+    }
+
+    Some(Step { kind, velocity, probability })
+}
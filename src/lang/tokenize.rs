@@ -1,5 +1,6 @@
 use std::iter::Peekable;
 use crate::operator::Operator;
+use crate::lang::note;
 use std::fmt::{ self, Display, Debug, Formatter };
 
 #[derive(Debug)]
@@ -8,6 +9,8 @@ pub struct TokenError {
     pub pos: (usize, usize),
 }
 
+// The contents are only read when debug printing
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum TokenErrorKind {
     ExpectedToken(char),
@@ -15,6 +18,9 @@ pub enum TokenErrorKind {
     UnexpectedToken(char),
     EmptyIdentifier,
     InvalidFloat,
+    InvalidUnit(String),
+    InvalidNote(String),
+    UnterminatedString,
     InvalidOperator,
 }
 
@@ -43,6 +49,8 @@ pub enum TokenKind {
     Separator(char),
     Identifier(String),
     Float(f32),
+    Beats(f32),
+    String(String),
     Block(BlockKind, Vec<Token>),
     Variable,
     CommandTerminator,
@@ -67,10 +75,11 @@ impl Display for TokenSlice<'_> {
     }
 }
 
-pub fn as_displayable_tokens<'a>(tokens: &'a [Token]) -> TokenSlice<'a> {
+pub fn as_displayable_tokens(tokens: &[Token]) -> TokenSlice<'_> {
     TokenSlice(tokens)
 }
 
+#[allow(dead_code)]
 pub fn print_tokens(tokens: &[Token]) {
     println!("{}", as_displayable_tokens(tokens));
 }
@@ -78,7 +87,8 @@ pub fn print_tokens(tokens: &[Token]) {
 #[derive(Debug, PartialEq)]
 pub enum BlockKind {
     Parenthesis,
-    Bracket
+    Bracket,
+    Brace,
 }
 
 impl Display for TokenKind {
@@ -91,17 +101,21 @@ impl Display for TokenKind {
             Separator(c) => write!(f, "{}", c)?,
             Identifier(s) => write!(f, "{}", s)?,
             Float(float) => write!(f, "{}", float)?,
+            Beats(beats) => write!(f, "{}b", beats)?,
+            String(string) => write!(f, "{:?}", string)?,
             Block(kind, contents) => {
                 write!(f, "{}", match kind {
                     BlockKind::Parenthesis => '(',
-                    BlockKind::Bracket => '['
+                    BlockKind::Bracket => '[',
+                    BlockKind::Brace => '{',
                 })?;
 
                 write!(f, "{}", as_displayable_tokens(&contents[..]))?;
 
                 write!(f, "{}", match kind {
                     BlockKind::Parenthesis => ')',
-                    BlockKind::Bracket => ']'
+                    BlockKind::Bracket => ']',
+                    BlockKind::Brace => '}',
                 })?;
             },
             CommandTerminator => write!(f, ";")?,
@@ -136,7 +150,7 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
             // See if it's the beginning of a token
             match c {
                 '(' => {
-                    let token_pos = pos.clone();
+                    let token_pos = *pos;
                     pos.1 += 1;
                     code.next();
                     let block_tokens = tokenize_setup(code, pos, Some(')'))?;
@@ -146,7 +160,7 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
                     });
                 },
                 '[' => {
-                    let token_pos = pos.clone();
+                    let token_pos = *pos;
                     pos.1 += 1;
                     code.next();
                     let block_tokens = tokenize_setup(code, pos, Some(']'))?;
@@ -155,10 +169,20 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
                         pos: token_pos
                     });
                 },
+                '{' => {
+                    let token_pos = *pos;
+                    pos.1 += 1;
+                    code.next();
+                    let block_tokens = tokenize_setup(code, pos, Some('}'))?;
+                    tokens.push(Token {
+                        kind: TokenKind::Block(BlockKind::Brace, block_tokens),
+                        pos: token_pos
+                    });
+                },
                 '$' => {
                     tokens.push(Token {
                         kind: TokenKind::Variable,
-                        pos: pos.clone()
+                        pos: *pos
                     });
                     pos.1 += 1;
                     code.next();
@@ -166,7 +190,7 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
                 ':' => {
                     tokens.push(Token {
                         kind: TokenKind::Assignment,
-                        pos: pos.clone()
+                        pos: *pos
                     });
                     pos.1 += 1;
                     code.next();
@@ -174,7 +198,7 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
                 ',' => {
                     tokens.push(Token {
                         kind: TokenKind::Separator(','),
-                        pos: pos.clone()
+                        pos: *pos
                     });
                     pos.1 += 1;
                     code.next();
@@ -182,37 +206,50 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
                 ';' => {
                     tokens.push(Token {
                         kind: TokenKind::CommandTerminator,
-                        pos: pos.clone()
+                        pos: *pos
                     });
                     pos.1 += 1;
                     code.next();
                 },
-                c if c.is_digit(10) || c == '.' => {
-                    let float_pos = pos.clone();
-                    let float = read_float(code, pos)?;
+                '"' => {
+                    let string_pos = *pos;
+                    let string = read_string(code, pos)?;
                     tokens.push(Token {
-                        kind: TokenKind::Float(float),
-                        pos: float_pos
+                        kind: TokenKind::String(string),
+                        pos: string_pos
+                    });
+                },
+                c if c.is_ascii_digit() || c == '.' => {
+                    let number_pos = *pos;
+                    let number = read_number(code, pos, false)?;
+                    tokens.push(Token {
+                        kind: number,
+                        pos: number_pos
                     });
                 },
                 c if c.is_alphabetic() || c == '_' => {
-                    let token_pos = pos.clone();
-                    let identifier = read_identifier(code, pos)?;
+                    let token_pos = *pos;
+                    let mut identifier = read_identifier(code, pos)?;
+                    // Whether something that looks like a note is a note or
+                    // a name is up to the parser, but the cents are read here
+                    if note::parse_note(&identifier).is_some() {
+                        read_cents(code, pos, &mut identifier)?;
+                    }
                     tokens.push(Token {
                         kind: TokenKind::Identifier(identifier),
                         pos: token_pos
                     });
                 },
                 _ => {
-                    let orig_pos = pos.clone();
+                    let orig_pos = *pos;
                     if let Ok(operator) = read_operator(code, pos) {
                         if operator == Operator::Sub {
                             if let Some(c) = code.peek() {
-                                if c.is_digit(10) {
-                                    let float = - read_float(code, pos)?;
+                                if c.is_ascii_digit() {
+                                    let number = read_number(code, pos, true)?;
                                     tokens.push(Token {
-                                        kind: TokenKind::Float(float),
-                                        pos: pos.clone()
+                                        kind: number,
+                                        pos: orig_pos
                                     });
                                     continue;
                                 }
@@ -235,7 +272,7 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
             if let Some(terminator) = terminator {
                 return Err(TokenError {
                     kind: TokenErrorKind::ExpectedToken(terminator),
-                    pos: pos.clone()
+                    pos: *pos
                 });
             }else{
                 break;
@@ -248,7 +285,7 @@ fn tokenize_setup(code: &mut Peekable<impl Iterator<Item = char>>,
 
 fn read_operator(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize, usize))
         -> Result<Operator, TokenError> {
-    let op_pos = pos.clone();
+    let op_pos = *pos;
     if let Some(&c) = code.peek() {
         use Operator::*;
         let operator = match c {
@@ -257,18 +294,31 @@ fn read_operator(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usi
             '*' => Some(Mult),
             '/' => Some(Div),
             '%' => Some(Mod),
+            '>' => Some(Greater),
+            '<' => Some(Less),
+            '=' => Some(Equal),
             _ => None,
         };
 
         if let Some(operator) = operator {
             // Since we read an operator, we should
             // increment the counter.
-            // NOTE: If there ever is an
-            // operator with 2 symbols, this code
-            // will have to change slightly
             code.next();
             pos.1 += 1;
 
+            // Equality is the only operator with 2 symbols, ``==``
+            if operator == Equal {
+                if let Some('=') = code.peek() {
+                    code.next();
+                    pos.1 += 1;
+                }else{
+                    return Err(TokenError {
+                        kind: TokenErrorKind::InvalidOperator,
+                        pos: op_pos,
+                    });
+                }
+            }
+
             Ok(operator)
         }else{
             Err(TokenError {
@@ -284,13 +334,53 @@ fn read_operator(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usi
     }
 }
 
+/// Reads a number, which may be a fraction like ``1/8``
+/// and may have a unit, i.e. ``250ms``, ``2s`` or ``1/4b``.
+/// Seconds are the default unit, and beats depend on the tempo,
+/// so they are left for the compiler to figure out.
+fn read_number(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize, usize), negative: bool)
+        -> Result<TokenKind, TokenError> {
+    let mut number = read_float(code, pos)?;
+    if negative {
+        number = -number;
+    }
+
+    if let Some('/') = code.peek() {
+        code.next();
+        pos.1 += 1;
+        number /= read_float(code, pos)?;
+    }
+
+    let unit_pos = *pos;
+    let mut unit = String::new();
+    while let Some(&c) = code.peek() {
+        if c.is_alphabetic() {
+            code.next();
+            pos.1 += 1;
+            unit.push(c);
+        }else{
+            break;
+        }
+    }
+
+    match unit.as_str() {
+        "" | "s" => Ok(TokenKind::Float(number)),
+        "ms" => Ok(TokenKind::Float(number / 1000.0)),
+        "b" => Ok(TokenKind::Beats(number)),
+        _ => Err(TokenError {
+            kind: TokenErrorKind::InvalidUnit(unit),
+            pos: unit_pos
+        }),
+    }
+}
+
 fn read_float(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize, usize))
         -> Result<f32, TokenError> {
-    let float_pos = pos.clone();
+    let float_pos = *pos;
     let mut temp_string = String::new();
     let mut contains_dot = false;
     while let Some(&c) = code.peek() {
-        if c.is_digit(10){
+        if c.is_ascii_digit(){
             temp_string.push(c);
             code.next();
             pos.1 += 1;
@@ -315,12 +405,87 @@ fn read_float(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize,
     }
 }
 
+/// Notes can be followed by an offset in cents, like ``A4+50c``, which
+/// is added to the note. There can't be any whitespace between the note
+/// and the offset, and that's the only place an operator can come right
+/// after a value.
+fn read_cents(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize, usize), note: &mut String)
+        -> Result<(), TokenError> {
+    let sign = match code.peek() {
+        Some('+') => 1.0,
+        Some('-') => -1.0,
+        _ => return Ok(()),
+    };
+
+    let cents_pos = *pos;
+    code.next();
+    pos.1 += 1;
+
+    let cents = read_float(code, pos)?;
+    if let Some('c') = code.peek() {
+        code.next();
+        pos.1 += 1;
+        note.push_str(&format!("{:+}c", sign * cents));
+        Ok(())
+    }else{
+        Err(TokenError {
+            kind: TokenErrorKind::InvalidNote(format!("{}{}", if sign > 0.0 { '+' } else { '-' }, cents)),
+            pos: cents_pos
+        })
+    }
+}
+
+/// Reads a string in quotes. A backslash makes
+/// the character after it a part of the string,
+/// so that quotes can be in strings.
+fn read_string(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize, usize))
+        -> Result<String, TokenError> {
+    let string_pos = *pos;
+    code.next();
+    pos.1 += 1;
+
+    let mut string = String::new();
+    let mut escaped = false;
+    for c in code.by_ref() {
+        if c == '\n' {
+            pos.1 = 0;
+            pos.0 += 1;
+        }else{
+            pos.1 += 1;
+        }
+
+        if escaped {
+            string.push(c);
+            escaped = false;
+        }else if c == '\\' {
+            escaped = true;
+        }else if c == '"' {
+            return Ok(string);
+        }else{
+            string.push(c);
+        }
+    }
+
+    Err(TokenError {
+        kind: TokenErrorKind::UnterminatedString,
+        pos: string_pos
+    })
+}
+
 fn read_identifier(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize, usize))
     -> Result<String, TokenError> {
-    let identifier_pos = pos.clone();
+    let identifier_pos = *pos;
     let mut identifier = String::new();
     while let Some(&c) = code.peek() {
-        if c.is_digit(10) || c.is_alphabetic() || c == '_' {
+        // Dots are allowed so that outputs of nodes can be
+        // accessed, like ``$clock.trig``
+        if c.is_ascii_digit() || c.is_alphabetic() || c == '_' || (c == '.' && !identifier.is_empty()) {
+            code.next();
+            pos.1 += 1;
+            identifier.push(c);
+        }else if c == '#' && note::is_note_letter(&identifier) {
+            // Sharp notes, like ``C#4``. Comments can
+            // therefore not start right after a note letter
             code.next();
             pos.1 += 1;
             identifier.push(c);
@@ -329,7 +494,7 @@ fn read_identifier(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (u
         }
     }
 
-    if identifier.len() == 0 {
+    if identifier.is_empty() {
         return Err(TokenError {
             kind: TokenErrorKind::EmptyIdentifier,
             pos: identifier_pos
@@ -342,7 +507,7 @@ fn read_identifier(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (u
 fn skip_whitespace(code: &mut Peekable<impl Iterator<Item = char>>, pos: &mut (usize, usize)) {
     while let Some(&value) = code.peek() {
         if value == '#' {
-            while let Some(value) = code.next() {
+            for value in code.by_ref() {
                 if value == '\n' {
                     pos.1 = 0;
                     pos.0 += 1;
@@ -3,32 +3,87 @@ use std::path::Path;
 mod synth;
 mod lang;
 mod operator;
+mod wav;
+
+/// The sample rate the synth runs at and the output file is written with
+const SAMPLE_RATE: usize = 48000;
 
 fn main() {
+    use std::collections::HashMap;
     use std::env;
-    use std::path::{ Path, PathBuf };
+    use std::path::PathBuf;
 
     let mut args = env::args();
     args.next();
-    if let Some(path) = args.next() {
+
+    let mut path = None;
+    let mut seed = None;
+    let mut inputs = HashMap::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(value) => seed = Some(value),
+                None => {
+                    println!("--seed needs a whole number after it");
+                    return;
+                },
+            },
+            "--input" => match args.next().as_ref().and_then(|v| v.split_once('=')) {
+                Some((name, file)) => {
+                    inputs.insert(name.to_string(), PathBuf::from(file));
+                },
+                None => {
+                    println!("--input needs a name and a file after it, like --input name=file.wav");
+                    return;
+                },
+            },
+            _ => path = Some(arg),
+        }
+    }
+
+    if let Some(path) = path {
         println!("How long should the generated file be?");
         let time = get_number();
 
         let mut path = PathBuf::from(path);
 
-        use operator::Operator;
+        let (synth, left_id, right_id) = match lang::compile_file(&path, SAMPLE_RATE as f32, seed, &inputs) {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            },
+        };
+        // The output goes next to the patch, and mustn't write over a file the patch reads
+        path.set_extension("wav");
+        if let Ok(output) = path.canonicalize() {
+            if synth.input_files().any(|file| file.path.canonicalize().is_ok_and(|input| input == output)) {
+                println!("The output file {} is also an input, so it can't be written over", path.display());
+                return;
+            }
+        }
 
-        let (synth, left_id, right_id) = lang::compile_file(&path).unwrap();
-        let mut executor = synth::ExecutionData::new(&synth, 48000);
+        let sample_rate = SAMPLE_RATE as f32;
+        let latency = synth.latency(left_id, sample_rate).max(synth.latency(right_id, sample_rate));
+        if latency > 0.0 {
+            println!("The output is {:.1}ms late", latency / sample_rate * 1000.0);
+        }
+
+        let mut executor = match synth::ExecutionData::new(&synth, SAMPLE_RATE) {
+            Ok(executor) => executor,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            },
+        };
 
         let mut samples = Vec::new();
-        for i in (0..(48000.0 * time) as usize) {
+        for _ in 0..(sample_rate * time) as usize {
             executor.run();
             samples.push((executor.get_data(left_id).unwrap(), executor.get_data(right_id).unwrap()));
         }
 
-        path.set_extension("wav");
-        write_to_wave(&path, &samples[..], 48000);
+        write_to_wave(&path, &samples[..], SAMPLE_RATE as u32);
     }
 }
 
@@ -41,14 +96,14 @@ fn get_number() -> f32 {
 fn write_to_wave(path: impl AsRef<Path>, data: &[(f32, f32)], sample_rate: u32) {
     let spec = hound::WavSpec {
         channels: 2,
-        sample_rate: sample_rate,
+        sample_rate,
         bits_per_sample: 32,
         sample_format: hound::SampleFormat::Float
     };
 
     let mut writer = hound::WavWriter::create(path, spec).unwrap();
     for (left, right) in data {
-        writer.write_sample(left.min(1.0).max(-1.0)).unwrap();
-        writer.write_sample(right.min(1.0).max(-1.0)).unwrap();
+        writer.write_sample(left.clamp(-1.0, 1.0)).unwrap();
+        writer.write_sample(right.clamp(-1.0, 1.0)).unwrap();
     }
 }
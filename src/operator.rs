@@ -5,6 +5,10 @@ pub enum Operator {
     Mult,
     Div,
     Mod,
+    // The comparisons are 1 when they are true and 0 otherwise
+    Greater,
+    Less,
+    Equal,
 }
 
 impl Operator {
@@ -17,6 +21,106 @@ impl Operator {
             Mult => a * b,
             Div => a / b,
             Mod => a % b,
+            Greater => bool_to_float(a > b),
+            Less => bool_to_float(a < b),
+            Equal => bool_to_float(a == b),
         }
     }
 }
+
+/// Math functions that are called like ``sin(x)``
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Function {
+    Abs,
+    Sin,
+    Cos,
+    Exp,
+    Log,
+    Sqrt,
+    Floor,
+    Sign,
+    DbToAmp,
+    AmpToDb,
+    Not,
+    Pow,
+    Min,
+    Max,
+    And,
+    Or,
+    Lerp,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Function> {
+        use Function::*;
+        Some(match name {
+            "abs" => Abs,
+            "sin" => Sin,
+            "cos" => Cos,
+            "exp" => Exp,
+            "log" => Log,
+            "sqrt" => Sqrt,
+            "floor" => Floor,
+            "sign" => Sign,
+            "db_to_amp" => DbToAmp,
+            "amp_to_db" => AmpToDb,
+            "not" => Not,
+            "pow" => Pow,
+            "min" => Min,
+            "max" => Max,
+            "and" => And,
+            "or" => Or,
+            "lerp" => Lerp,
+            _ => return None,
+        })
+    }
+
+    pub fn n_args(&self) -> usize {
+        use Function::*;
+        match self {
+            Abs | Sin | Cos | Exp | Log | Sqrt | Floor | Sign | DbToAmp | AmpToDb | Not => 1,
+            Pow | Min | Max | And | Or => 2,
+            Lerp => 3,
+        }
+    }
+
+    /// Things are true when they are above 0, like gates
+    #[inline]
+    pub fn evaluate(&self, args: &[f32]) -> f32 {
+        use Function::*;
+        match self {
+            Abs => args[0].abs(),
+            Sin => args[0].sin(),
+            Cos => args[0].cos(),
+            Exp => args[0].exp(),
+            Log => args[0].ln(),
+            Sqrt => args[0].sqrt(),
+            Floor => args[0].floor(),
+            Sign => if args[0] > 0.0 { 1.0 } else if args[0] < 0.0 { -1.0 } else { 0.0 },
+            DbToAmp => db_to_amp(args[0]),
+            AmpToDb => amp_to_db(args[0]),
+            Not => bool_to_float(args[0] <= 0.0),
+            Pow => args[0].powf(args[1]),
+            Min => args[0].min(args[1]),
+            Max => args[0].max(args[1]),
+            And => bool_to_float(args[0] > 0.0 && args[1] > 0.0),
+            Or => bool_to_float(args[0] > 0.0 || args[1] > 0.0),
+            Lerp => args[0] + (args[1] - args[0]) * args[2],
+        }
+    }
+}
+
+#[inline]
+pub fn bool_to_float(value: bool) -> f32 {
+    if value { 1.0 } else { 0.0 }
+}
+
+#[inline]
+pub fn amp_to_db(amp: f32) -> f32 {
+    20.0 * amp.abs().max(1e-10).log10()
+}
+
+#[inline]
+pub fn db_to_amp(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
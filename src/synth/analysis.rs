@@ -0,0 +1,132 @@
+// Nodes that listen to a signal and turn what they hear into a control signal.
+//
+// The envelope follower moves towards the level of the signal at the attack
+// or release speed. For the peak level it follows the absolute value, and
+// for the rms level it follows the square root of the average of the square.
+// The data is [envelope, average square].
+//
+// The pitch tracker uses the YIN algorithm. The buffer is the last two
+// periods of the lowest frequency it can find, followed by some space for
+// the differences at every lag. Every half period it looks for the lag where
+// the signal is the most like itself. The data is [write position, samples
+// until the next analysis, frequency, clarity].
+
+use super::dynamics::smoothing;
+
+pub const ENV_FOLLOW_N_DATA: usize = 2;
+
+/// How long the square is averaged over for the rms level, in seconds
+const RMS_TIME: f32 = 0.02;
+pub const PITCH_TRACK_N_DATA: usize = 4;
+
+const WRITE: usize = 0;
+const UNTIL_ANALYSIS: usize = 1;
+const FREQ: usize = 2;
+const CLARITY: usize = 3;
+
+/// The inputs are the signal, the attack time and the release time
+pub fn env_follow(rms: bool,
+                  data: &mut [f32],
+                  outputs: &mut [f32],
+                  inputs: &[f32],
+                  dt_per_sample: f32) {
+    let level = if rms {
+        data[1] += (inputs[0] * inputs[0] - data[1]) * smoothing(RMS_TIME, dt_per_sample);
+        data[1].max(0.0).sqrt()
+    }else{
+        inputs[0].abs()
+    };
+
+    let time = if level > data[0] { inputs[1] } else { inputs[2] };
+    data[0] += (level - data[0]) * smoothing(time, dt_per_sample);
+    outputs[0] = data[0];
+}
+
+/// The settings of a pitch tracker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PitchTracker {
+    pub min_freq: f32,
+    pub max_freq: f32,
+    /// How unclear the pitch can be and still count, from 0 to 1
+    pub threshold: f32,
+}
+
+/// The longest lag that is looked at, in samples
+fn max_lag(min_freq: f32, sample_rate: f32) -> usize {
+    (sample_rate / min_freq).ceil() as usize + 1
+}
+
+pub fn pitch_track_buffer_size(min_freq: f32, sample_rate: f32) -> usize {
+    3 * max_lag(min_freq, sample_rate) + 1
+}
+
+/// The input is the signal, and the outputs are the frequency and how
+/// clear the pitch is, from 0 to 1. When there is no clear pitch the last
+/// frequency is kept.
+pub fn pitch_track(tracker: &PitchTracker,
+                   data: &mut [f32],
+                   buffer: &mut [f32],
+                   outputs: &mut [f32],
+                   inputs: &[f32],
+                   dt_per_sample: f32) {
+    let sample_rate = 1.0 / dt_per_sample;
+    let max_lag = max_lag(tracker.min_freq, sample_rate);
+    let min_lag = ((sample_rate / tracker.max_freq).floor() as usize).max(2);
+    let (history, diffs) = buffer.split_at_mut(2 * max_lag);
+
+    let write = data[WRITE] as usize % history.len();
+    history[write] = inputs[0];
+    data[WRITE] = ((write + 1) % history.len()) as f32;
+
+    data[UNTIL_ANALYSIS] -= 1.0;
+    if data[UNTIL_ANALYSIS] <= 0.0 {
+        data[UNTIL_ANALYSIS] = (max_lag / 2) as f32;
+
+        // The oldest sample is right after the newest one
+        let start = write + 1;
+        let at = |i: usize| history[(start + i) % history.len()];
+
+        // The difference between the signal and itself ``lag`` samples later,
+        // divided by the average difference of the shorter lags
+        let mut sum = 0.0;
+        diffs[0] = 1.0;
+        for (lag, diff) in diffs.iter_mut().enumerate().skip(1) {
+            let difference: f32 = (0..max_lag).map(|i| {
+                let d = at(i) - at(i + lag);
+                d * d
+            }).sum();
+            sum += difference;
+            *diff = if sum > 0.0 { difference * lag as f32 / sum } else { 1.0 };
+        }
+
+        // The first dip below the threshold, or the lowest one if there isn't one
+        let mut best = None;
+        let mut lowest = min_lag;
+        for lag in min_lag..max_lag {
+            if diffs[lag] < diffs[lowest] {
+                lowest = lag;
+            }
+            if diffs[lag] < tracker.threshold {
+                let mut lag = lag;
+                while lag + 1 < max_lag && diffs[lag + 1] < diffs[lag] {
+                    lag += 1;
+                }
+                best = Some(lag);
+                break;
+            }
+        }
+        let lag = best.unwrap_or(lowest);
+
+        if best.is_some() {
+            // Fit a parabola to find the dip between samples
+            let (a, b, c) = (diffs[lag - 1], diffs[lag], diffs[lag + 1]);
+            let curve = a - 2.0 * b + c;
+            let offset = if curve > 0.0 { (0.5 * (a - c) / curve).clamp(-0.5, 0.5) } else { 0.0 };
+            data[FREQ] = sample_rate / (lag as f32 + offset);
+        }
+        data[CLARITY] = (1.0 - diffs[lag]).clamp(0.0, 1.0);
+    }
+
+    outputs[0] = data[FREQ];
+    outputs[1] = data[CLARITY];
+}
@@ -0,0 +1,58 @@
+// Choosing between branches of the synth, so that only the chosen
+// branch has to be calculated.
+//
+// The ``Branch`` node comes right before the nodes of the branches, and
+// picks which of them to run. Its data is [current branch, previous branch,
+// how far the crossfade between them has come], where the crossfade is 1
+// when it's done. The previous branch keeps running until the crossfade is
+// done. The ``BranchOutput`` node comes after the branches, and reads the
+// data of the ``Branch`` node as its first three inputs, followed by the
+// outputs of the branches.
+
+pub const N_DATA: usize = 3;
+pub const OUTPUT_STATE_INPUTS: usize = 3;
+
+/// The data the branch node starts with, no branch has been picked yet
+pub const INITIAL_DATA: [f32; N_DATA] = [-1.0, 0.0, 1.0];
+
+const CURRENT: usize = 0;
+const PREVIOUS: usize = 1;
+const FADE: usize = 2;
+
+/// The input is the index of the branch
+pub fn evaluate(n_branches: usize, fade_time: f32, data: &mut [f32], inputs: &[f32], dt_per_sample: f32) {
+    let chosen = inputs[0].floor().clamp(0.0, n_branches.saturating_sub(1) as f32);
+
+    if data[CURRENT] < 0.0 {
+        data[CURRENT] = chosen;
+        data[FADE] = 1.0;
+    }else if chosen != data[CURRENT] {
+        data[PREVIOUS] = data[CURRENT];
+        data[CURRENT] = chosen;
+        data[FADE] = 0.0;
+    }
+
+    if fade_time > 0.0 {
+        data[FADE] = (data[FADE] + dt_per_sample / fade_time).min(1.0);
+    }else{
+        data[FADE] = 1.0;
+    }
+}
+
+/// If a branch needs to be calculated
+#[inline]
+pub fn is_active(data: &[f32], branch: usize) -> bool {
+    let branch = branch as f32;
+    data[CURRENT] == branch || (data[FADE] < 1.0 && data[PREVIOUS] == branch)
+}
+
+pub fn evaluate_output(outputs: &mut [f32], inputs: &[f32]) {
+    let (state, branches) = inputs.split_at(OUTPUT_STATE_INPUTS);
+    let current = branches[state[CURRENT] as usize];
+    outputs[0] = if state[FADE] < 1.0 {
+        let previous = branches[state[PREVIOUS] as usize];
+        previous + (current - previous) * state[FADE]
+    }else{
+        current
+    };
+}
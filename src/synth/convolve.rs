@@ -0,0 +1,147 @@
+// Convolution with an impulse response, for reverbs and cabinets.
+//
+// This is uniformly partitioned overlap-save convolution. The impulse
+// response is resampled to the sample rate of the node, and split into blocks, and the spectra of those are worked out
+// when the patch is compiled. While running, the input is gathered into
+// blocks, and every time a block is full its spectrum is multiplied with
+// the spectra of the impulse response blocks and the spectra of the input
+// blocks before it. This makes the output ``block_size`` samples late.
+//
+// The data is [position in the block, newest input spectrum], and the
+// buffer is laid out like this:
+// [last input block, input block, output block, 
+//  input spectra real, input spectra imaginary, work real, work imaginary]
+
+use super::fft::fft;
+
+pub struct ConvolutionData {
+    pub block_size: usize,
+    // The spectra of the blocks of the impulse response, each of them
+    // is 2 * block_size long.
+    pub spectra_re: Vec<Vec<f32>>,
+    pub spectra_im: Vec<Vec<f32>>,
+}
+
+// The spectra are big, so they are not printed
+impl std::fmt::Debug for ConvolutionData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ConvolutionData ( block_size: {}, blocks: {} )", self.block_size, self.spectra_re.len())
+    }
+}
+
+impl PartialEq for ConvolutionData {
+    fn eq(&self, other: &ConvolutionData) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl ConvolutionData {
+    /// The impulse response is at ``impulse_rate``, and is resampled to
+    /// the sample rate the node runs at. The block size has to be a power of two.
+    pub fn new(impulse: &[f32], impulse_rate: f32, sample_rate: f32, block_size: usize) -> ConvolutionData {
+        let impulse = resample(impulse, impulse_rate, sample_rate);
+        let fft_size = 2 * block_size;
+        let mut spectra_re = Vec::new();
+        let mut spectra_im = Vec::new();
+        for block in impulse.chunks(block_size) {
+            let mut re = vec![0.0; fft_size];
+            let mut im = vec![0.0; fft_size];
+            re[..block.len()].copy_from_slice(block);
+            fft(&mut re, &mut im, false);
+            spectra_re.push(re);
+            spectra_im.push(im);
+        }
+
+        ConvolutionData { block_size, spectra_re, spectra_im }
+    }
+
+    fn n_blocks(&self) -> usize {
+        self.spectra_re.len()
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        let fft_size = 2 * self.block_size;
+        3 * self.block_size + 2 * self.n_blocks() * fft_size + 2 * fft_size
+    }
+}
+
+/// Changes the sample rate of an impulse response by reading between its samples,
+/// like ``sample`` plays files. There are more samples at higher sample rates, so
+/// they are made quieter to keep the volume of the convolution the same.
+fn resample(impulse: &[f32], from: f32, to: f32) -> Vec<f32> {
+    if from == to {
+        return impulse.to_vec();
+    }
+
+    let step = from / to;
+    let length = ((impulse.len() as f32 / step).ceil() as usize).max(1);
+    (0..length).map(|i| {
+        let position = i as f32 * step;
+        let index = position as usize;
+        let fraction = position - index as f32;
+        let a = impulse.get(index).copied().unwrap_or(0.0);
+        let b = impulse.get(index + 1).copied().unwrap_or(0.0);
+        (a + (b - a) * fraction) * step
+    }).collect()
+}
+
+pub const N_DATA: usize = 2;
+
+/// The inputs are the signal and the wet/dry mix
+pub fn evaluate(conv: &ConvolutionData,
+                data: &mut [f32],
+                buffer: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32]) {
+    let block_size = conv.block_size;
+    let fft_size = 2 * block_size;
+    let n_blocks = conv.n_blocks();
+
+    let (input, rest) = buffer.split_at_mut(2 * block_size);
+    let (output, rest) = rest.split_at_mut(block_size);
+    let (history_re, rest) = rest.split_at_mut(n_blocks * fft_size);
+    let (history_im, rest) = rest.split_at_mut(n_blocks * fft_size);
+    let (work_re, work_im) = rest.split_at_mut(fft_size);
+
+    let position = data[0] as usize;
+    input[block_size + position] = inputs[0];
+    let mix = inputs[1].clamp(0.0, 1.0);
+    outputs[0] = inputs[0] * (1.0 - mix) + output[position] * mix;
+
+    if position + 1 < block_size {
+        data[0] = (position + 1) as f32;
+        return;
+    }
+    data[0] = 0.0;
+
+    // The spectrum of the last two blocks of input goes into the history,
+    // which is a ring buffer of spectra
+    let newest = (data[1] as usize + 1) % n_blocks;
+    data[1] = newest as f32;
+    work_re.copy_from_slice(input);
+    work_im.iter_mut().for_each(|v| *v = 0.0);
+    fft(work_re, work_im, false);
+    history_re[newest * fft_size..(newest + 1) * fft_size].copy_from_slice(work_re);
+    history_im[newest * fft_size..(newest + 1) * fft_size].copy_from_slice(work_im);
+    input.copy_within(block_size.., 0);
+
+    // Multiply every input spectrum with the impulse response block
+    // that is as many blocks back
+    work_re.iter_mut().for_each(|v| *v = 0.0);
+    work_im.iter_mut().for_each(|v| *v = 0.0);
+    for block in 0..n_blocks {
+        let spectrum = (newest + n_blocks - block) % n_blocks * fft_size;
+        let x_re = &history_re[spectrum..spectrum + fft_size];
+        let x_im = &history_im[spectrum..spectrum + fft_size];
+        let h_re = &conv.spectra_re[block];
+        let h_im = &conv.spectra_im[block];
+        for i in 0..fft_size {
+            work_re[i] += x_re[i] * h_re[i] - x_im[i] * h_im[i];
+            work_im[i] += x_re[i] * h_im[i] + x_im[i] * h_re[i];
+        }
+    }
+
+    // The first half wrapped around, so only the second half is kept
+    fft(work_re, work_im, true);
+    output.copy_from_slice(&work_re[block_size..]);
+}
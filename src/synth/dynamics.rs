@@ -0,0 +1,115 @@
+// Dynamics processing: compressors, gates and limiters.
+//
+// The compressor and the gate find how many decibels to turn the signal
+// down from the level of the sidechain, and move towards that at the
+// attack or release speed. Their data is just [gain reduction in decibels].
+//
+// The limiter looks ahead, by delaying the signal and turning it down
+// before the peaks get there. The data is [position, released gain, sum
+// of the box filter, position in the needed gains], and the buffer has
+// three lines, one for the delayed signal, one for the gain that each sample
+// needs, and one for the gain after holding and releasing it, which goes
+// through a box filter. The needed gains are one sample longer than the
+// others, so that the gain is held for all of the box filter.
+
+use crate::operator::{ amp_to_db, db_to_amp };
+
+/// The most the gate turns the signal down, in decibels
+const MAX_GATE_REDUCTION: f32 = 100.0;
+
+pub const N_DATA: usize = 1;
+pub const LIMITER_N_DATA: usize = 4;
+
+/// How far a one pole smoother moves every sample to reach
+/// about 63% of the way in ``time`` seconds
+#[inline]
+pub fn smoothing(time: f32, dt_per_sample: f32) -> f32 {
+    if time <= 0.0 {
+        1.0
+    }else{
+        1.0 - (-dt_per_sample / time).exp()
+    }
+}
+
+/// The inputs are the signal, the sidechain, the threshold in decibels, the ratio,
+/// the attack and release times and the makeup gain in decibels.
+pub fn evaluate_compressor(data: &mut [f32],
+                           outputs: &mut [f32],
+                           inputs: &[f32],
+                           dt_per_sample: f32) {
+    let level = amp_to_db(inputs[1]);
+    let ratio = inputs[3].max(1.0);
+    let target = (level - inputs[2]).max(0.0) * (1.0 - 1.0 / ratio);
+
+    let time = if target > data[0] { inputs[4] } else { inputs[5] };
+    data[0] += (target - data[0]) * smoothing(time, dt_per_sample);
+    outputs[0] = inputs[0] * db_to_amp(inputs[6] - data[0]);
+}
+
+/// A downwards expander, which turns the signal down by ``ratio`` decibels for
+/// every decibel that the sidechain is below the threshold. High ratios make
+/// it a gate. The inputs are like the inputs of the compressor.
+pub fn evaluate_gate(data: &mut [f32],
+                     outputs: &mut [f32],
+                     inputs: &[f32],
+                     dt_per_sample: f32) {
+    let level = amp_to_db(inputs[1]);
+    let ratio = inputs[3].max(1.0);
+    let target = ((inputs[2] - level).max(0.0) * (ratio - 1.0)).min(MAX_GATE_REDUCTION);
+
+    // Opening the gate is the attack
+    let time = if target < data[0] { inputs[4] } else { inputs[5] };
+    data[0] += (target - data[0]) * smoothing(time, dt_per_sample);
+    outputs[0] = inputs[0] * db_to_amp(inputs[6] - data[0]);
+}
+
+pub fn limiter_buffer_size(lookahead: f32, sample_rate: f32) -> usize {
+    3 * limiter_length(lookahead, sample_rate) + 1
+}
+
+pub fn limiter_length(lookahead: f32, sample_rate: f32) -> usize {
+    ((lookahead * sample_rate) as usize).max(1)
+}
+
+/// The inputs are the signal, the ceiling in decibels and the release time.
+/// The output is ``lookahead`` seconds late.
+pub fn evaluate_limiter(lookahead: f32,
+                        data: &mut [f32],
+                        buffer: &mut [f32],
+                        outputs: &mut [f32],
+                        inputs: &[f32],
+                        dt_per_sample: f32) {
+    let len = limiter_length(lookahead, 1.0 / dt_per_sample);
+    let (delay, rest) = buffer.split_at_mut(len);
+    let (needed, held) = rest.split_at_mut(len + 1);
+
+    let position = data[0] as usize % len;
+    let ceiling = db_to_amp(inputs[1]);
+
+    // The oldest sample goes out, and the new one comes in
+    let delayed = delay[position];
+    delay[position] = inputs[0];
+    let needed_position = data[3] as usize % (len + 1);
+    needed[needed_position] = (ceiling / inputs[0].abs().max(1e-10)).min(1.0);
+    data[3] = ((needed_position + 1) % (len + 1)) as f32;
+
+    // The lowest gain any sample in the lookahead needs, which is let back
+    // up slowly, and then smoothed with a box filter as long as the lookahead,
+    // so that the gain is low enough right when the peak gets out.
+    let hold = needed.iter().copied().fold(1.0, f32::min);
+    if hold < data[1] {
+        data[1] = hold;
+    }else{
+        data[1] += (hold - data[1]) * smoothing(inputs[2], dt_per_sample);
+    }
+
+    data[2] += data[1] - held[position];
+    held[position] = data[1];
+    if position == 0 {
+        // Add it up again now and then, so that rounding errors don't build up
+        data[2] = held.iter().sum();
+    }
+
+    data[0] = ((position + 1) % len) as f32;
+    outputs[0] = delayed * (data[2] / len as f32).min(1.0);
+}
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
-use super::{ NodeKind, Node, MAX_INPUTS, Synth, Id, MaybeId, Probe };
+use super::{ Synth, Id, NodeKind };
+use super::branch;
+use super::input::{ InputStream, InputError };
+use super::interpolate::{ self, Interpolation };
 
 // TODO: Give this type a nice debug print, that graphs the probes and stuff :)
-#[derive(Clone)]
 pub struct ExecutionData<'a> {
     node_data: Vec<f32>,
 
@@ -13,21 +15,58 @@ pub struct ExecutionData<'a> {
     // vector)
     probes: HashMap<Id, ProbeData>,
 
+    // A place to gather the inputs of a node before running it,
+    // it's long enough to fit the inputs of any node in the synth
+    input_buffer: Vec<f32>,
+
+    // The internal buffers of the nodes, one after the other. The buffer
+    // of node ``n`` goes from ``buffer_locs[n]`` to ``buffer_locs[n + 1]``.
+    buffers: Vec<f32>,
+    buffer_locs: Vec<usize>,
+
+    // Where to go when reaching a node, which is the node itself unless
+    // it's the start of a branch that isn't running.
+    jumps: Vec<usize>,
+
+    // The files that are played into the input nodes, and
+    // where the outputs of those nodes are
+    input_streams: Vec<(usize, InputStream)>,
+
     sampling_rate: usize,
 
     synth: &'a Synth,
 }
 
 impl ExecutionData<'_> {
-    pub fn new<'a>(synth: &'a Synth, sampling_rate: usize) -> ExecutionData<'a> {
-        // Set up the probes
+    /// Fails if one of the input files of the synth can't be opened
+    pub fn new(synth: &Synth, sampling_rate: usize) -> Result<ExecutionData<'_>, InputError> {
+        // The innermost oversample node that every node is in
+        let mut oversampled_by = Vec::with_capacity(synth.nodes.len());
+        let mut oversampled: Vec<(usize, usize)> = Vec::new();
+        for (i, node) in synth.nodes.iter().enumerate() {
+            while oversampled.last().is_some_and(|&(end, _)| i >= end) {
+                oversampled.pop();
+            }
+            oversampled_by.push(oversampled.last().map(|&(_, node)| node));
+
+            if let NodeKind::Oversample(end, _) = node.kind {
+                oversampled.push((end, i));
+            }
+        }
+        let speeds = synth.node_speeds();
+
+        // Set up the probes. Probes of oversampled nodes record
+        // every time the node runs, so they need more room.
         let mut probes = HashMap::with_capacity(synth.probes.len());
         for (&key, probe) in synth.probes.iter() {
-            probes.insert(key, ProbeData {
-                data: vec![0.0; (probe.max_time * sampling_rate as f32).floor() as usize],
-                data_start: 0,
-                probing: probe.probing
-            });
+            let node = synth.data_allocations[key.as_usize()].get_usize();
+            let speed = node.map_or(1, |node| speeds[node]);
+            probes.insert(key, ProbeData::new(
+                ((probe.max_time * (sampling_rate * speed) as f32).floor() as usize).max(1),
+                probe.probing,
+                speed as f32,
+                node.and_then(|node| oversampled_by[node])
+            ));
         }
 
         println!("{:?}", &probes);
@@ -36,44 +75,108 @@ impl ExecutionData<'_> {
         // This is important as the initial data may contain
         // startup information that changes the function
         // of the synth
-        let mut data = synth.initial_data.clone();
+        let data = synth.initial_data.clone();
 
-        ExecutionData {
-            sampling_rate: sampling_rate,
-            node_data: data,
-            probes: probes,
-            synth: synth,
+        // Oversampled nodes need buffers for their higher sample rate
+        let mut buffer_locs = Vec::with_capacity(synth.nodes.len() + 1);
+        let mut buffer_size = 0;
+        let mut input_streams = Vec::new();
+        buffer_locs.push(0);
+        for (node, speed) in synth.nodes.iter().zip(speeds) {
+            buffer_size += node.kind.buffer_size((sampling_rate * speed) as f32);
+            buffer_locs.push(buffer_size);
+
+            if let NodeKind::Input(file) = &node.kind {
+                input_streams.push((node.get_output_loc(0).unwrap(), InputStream::open(file, sampling_rate as f32)?));
+            }
         }
+
+        Ok(ExecutionData {
+            sampling_rate,
+            node_data: data,
+            probes,
+            input_buffer: vec![0.0; synth.max_inputs],
+            buffers: vec![0.0; buffer_size],
+            buffer_locs,
+            jumps: (0..synth.nodes.len()).collect(),
+            input_streams,
+            synth,
+        })
     }
 
     pub fn get_data(&self, id: Id) -> Option<f32> {
-        self.node_data.get(id.as_usize()).map(|v| *v)
+        self.node_data.get(id.as_usize()).copied()
     }
 
     pub fn run(&mut self) {
+        let dt_per_sample = 1.0 / self.sampling_rate as f32;
+
+        // The inputs keep playing even when they are in a branch that isn't running
+        for (loc, stream) in self.input_streams.iter_mut() {
+            self.node_data[*loc] = stream.next_sample();
+        }
+
+        self.run_nodes(0, self.synth.nodes.len(), dt_per_sample);
+        self.update_probes(None);
+    }
+
+    /// Updates the probes of the nodes that are oversampled by
+    /// the given oversample node, or that aren't oversampled.
+    fn update_probes(&mut self, oversampled_by: Option<usize>) {
+        for (id, probe) in self.probes.iter_mut() {
+            if probe.oversampled_by == oversampled_by {
+                // Get the value that you want to probe
+                probe.add_data(self.node_data[id.as_usize()]);
+            }
+        }
+    }
+
+    /// Runs the nodes from ``from`` up to ``to``. Oversampled nodes are
+    /// run several times, with a shorter time per sample.
+    fn run_nodes(&mut self, from: usize, to: usize, dt_per_sample: f32) {
         let synth = self.synth;
-        let data = &mut self.node_data;
-        let probes = &mut self.probes;
         let sample_rate = self.sampling_rate as f32; // Convert it to f32 here instead of later to only have to do it once
-        let dt_per_sample = 1.0 / self.sampling_rate as f32;
 
-        let mut inputs = [0f32; MAX_INPUTS];
-        for node in self.synth.nodes.iter() {
-            // Gather all the inputs
-            for (i, input) in node.inputs.iter().enumerate() {
-                if let Some(id) = input.get() {
-                    inputs[i] = data[id.0 as usize];
+        let mut i = from;
+        while i < to {
+            if self.jumps[i] != i {
+                i = self.jumps[i];
+                continue;
+            }
+            let node = &synth.nodes[i];
+
+            if let NodeKind::Oversample(oversampled_end, factor) = node.kind {
+                for _ in 0..factor {
+                    self.run_nodes(i + 1, oversampled_end, dt_per_sample / factor as f32);
+                    self.update_probes(Some(i));
                 }
+                i = oversampled_end;
+                continue;
+            }
+
+            let data = &mut self.node_data;
+            let probes = &self.probes;
+            let inputs = &mut self.input_buffer;
+
+            // Gather all the inputs. Inputs that aren't
+            // connected to anything are zero.
+            let (start, end) = node.get_input_range();
+            for (input, id) in inputs.iter_mut().zip(&synth.inputs[start..end]) {
+                *input = match id.get_usize() {
+                    Some(id) => data[id],
+                    None => 0.0,
+                };
             }
 
             // Gather all the data that the node needs
             let (start, end) = node.get_allocated_range();
-            let mut range = &mut data[start..end];
+            let range = &mut data[start..end];
             
             // Because the data looks like:
             // [data, data, data, output, output],
             // we want to split this such that this pattern holds.
-            let (mut data, mut outputs) = range.split_at_mut(node.kind.n_data_allocations());
+            let (data, outputs) = range.split_at_mut(node.kind.n_data_allocations());
+            let buffer = &mut self.buffers[self.buffer_locs[i]..self.buffer_locs[i + 1]];
 
             // Because we made sure that all the inputs are
             // of right length(we checked them against the node.kind
@@ -85,22 +188,30 @@ impl ExecutionData<'_> {
             // really be unsafe)
             unsafe {
                 node.kind.evaluate(
-                    |id, time| {
+                    |id, time, interpolation| {
+                        // The newest value in the probe is from one sample ago,
+                        // at the sample rate of the node it's probing
                         let id = synth.probe_id_map.get(&id)?;
-                        probes.get(id).map(|v| v.get_data((time * sample_rate).floor() as usize)).flatten()
+                        probes.get(id).and_then(|v| v.get_interpolated(time * sample_rate * v.speed - 1.0, interpolation))
                     },
                     data,
+                    buffer,
                     outputs,
                     &inputs[0..node.kind.n_inputs()],
                     dt_per_sample
                     );
             }
-        }
 
-        // Update all the probes
-        for (id, probe) in probes.iter_mut() {
-            // Get the value that you want to probe
-            probe.add_data(data[id.as_usize()]);
+            // Skip the branches that aren't chosen
+            if let NodeKind::Branch(ranges, _) = &node.kind {
+                for (b, &(start, end)) in ranges.iter().enumerate() {
+                    if start < end {
+                        self.jumps[start] = if branch::is_active(data, b) { start } else { end };
+                    }
+                }
+            }
+
+            i += 1;
         }
     }
 
@@ -109,8 +220,9 @@ impl ExecutionData<'_> {
     /// is that you may want more channels from your synth, and it's kind of
     /// inconsistant to get the channels from the run method but get the other
     /// channels from this function, so I combined them.
-    pub fn get_node_output(&self, node: &Node, output: usize) -> Option<f32> {
-        node.get_output_loc(output).map(|v| self.node_data.get(v).map(|v| *v)).flatten()
+    #[allow(dead_code)]
+    pub fn get_node_output(&self, node: &super::Node, output: usize) -> Option<f32> {
+        node.get_output_loc(output).and_then(|v| self.node_data.get(v).copied())
     }
 }
 
@@ -118,7 +230,11 @@ impl ExecutionData<'_> {
 pub struct ProbeData {
     data_start: usize,
     data: Vec<f32>,
-    probing: Id
+    probing: Id,
+    // How many times faster than the synth the probed node runs,
+    // and the oversample node that runs it, if there is one
+    speed: f32,
+    oversampled_by: Option<usize>,
 }
 
 impl std::fmt::Debug for ProbeData {
@@ -128,11 +244,13 @@ impl std::fmt::Debug for ProbeData {
 }
 
 impl ProbeData {
-    pub fn new(size: usize, probing: Id) -> ProbeData {
+    pub fn new(size: usize, probing: Id, speed: f32, oversampled_by: Option<usize>) -> ProbeData {
         ProbeData {
             data: vec![0.0; size],
-            probing: probing,
+            probing,
             data_start: 0,
+            speed,
+            oversampled_by,
         }
     }
 
@@ -151,6 +269,32 @@ impl ProbeData {
         }
     }
 
+    /// Gets data between two locations. Locations past the end are
+    /// the last piece of data, so that reading right at the end works.
+    pub fn get_interpolated(&self, loc: f32, interpolation: Interpolation) -> Option<f32> {
+        let index = loc.max(0.0).floor();
+        let t = loc.max(0.0) - index;
+        let index = index as usize;
+        let get = |loc: usize| self.get_data(loc.min(self.data.len() - 1));
+
+        match interpolation {
+            Interpolation::None => self.get_data(index),
+            Interpolation::Linear | Interpolation::Allpass => {
+                Some(interpolate::linear(self.get_data(index)?, get(index + 1)?, t))
+            },
+            Interpolation::Cubic => {
+                let b = self.get_data(index)?;
+                let c = get(index + 1)?;
+                // The sample after the newest one doesn't exist yet, so guess it
+                let a = match index {
+                    0 => 2.0 * b - c,
+                    _ => self.get_data(index - 1)?,
+                };
+                Some(interpolate::cubic(a, b, c, get(index + 2)?, t))
+            },
+        }
+    }
+
     pub fn add_data(&mut self, data: f32) {
         // A wrapping subtraction
         // has to be done since usize cannot be less than 0,
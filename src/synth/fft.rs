@@ -0,0 +1,54 @@
+// A radix-2 fast fourier transform, for the nodes that work on spectra.
+// The real and imaginary parts are kept in separate slices, so that they
+// can live in the internal buffers of nodes.
+
+use std::f32::consts::PI;
+
+/// Transforms in place. The length has to be a power of two. The inverse
+/// transform is scaled, so that going forwards and back gives the same data.
+pub fn fft(re: &mut [f32], im: &mut [f32], inverse: bool) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+    if n <= 1 {
+        return;
+    }
+
+    // Bit reversed order
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut size = 2;
+    while size <= n {
+        let half = size / 2;
+        let angle = sign * 2.0 * PI / size as f32;
+        for k in 0..half {
+            let (w_im, w_re) = (angle * k as f32).sin_cos();
+            for start in (0..n).step_by(size) {
+                let a = start + k;
+                let b = a + half;
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        size *= 2;
+    }
+
+    if inverse {
+        let scale = 1.0 / n as f32;
+        for (re, im) in re.iter_mut().zip(im.iter_mut()) {
+            *re *= scale;
+            *im *= scale;
+        }
+    }
+}
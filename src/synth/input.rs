@@ -0,0 +1,84 @@
+// Audio files that are played into the synth while it runs, so that the
+// synth can be used as an effect.
+//
+// The files are streamed, one frame at a time, instead of being read all at
+// once like samples. When the file has another sample rate than the synth,
+// it's read between the frames with linear interpolation. After the end of
+// the file, or if reading it fails partway through, the input is silent.
+
+use std::fmt;
+use std::path::PathBuf;
+use crate::wav::WaveStream;
+
+/// An input file of a synth, and the channel to read from it
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub channel: Option<usize>,
+}
+
+/// An input file that couldn't be opened when the synth started running
+#[derive(Debug)]
+pub struct InputError {
+    pub path: PathBuf,
+    pub error: hound::Error,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not read the input file {}: {}", self.path.display(), self.error)
+    }
+}
+
+pub struct InputStream {
+    stream: Option<WaveStream>,
+    // How many frames of the file go by every sample
+    step: f32,
+    // How far between the previous and the next frame we are
+    position: f32,
+    previous: f32,
+    next: f32,
+}
+
+impl InputStream {
+    pub fn open(file: &InputFile, sample_rate: f32) -> Result<InputStream, InputError> {
+        let stream = WaveStream::open(&file.path, file.channel).map_err(|error| InputError {
+            path: file.path.clone(),
+            error,
+        })?;
+        let step = stream.sample_rate() as f32 / sample_rate;
+        let mut stream = Some(stream);
+        let previous = read_frame(&mut stream);
+        let next = read_frame(&mut stream);
+        Ok(InputStream {
+            stream,
+            step,
+            position: 0.0,
+            previous,
+            next,
+        })
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = self.previous + (self.next - self.previous) * self.position;
+        self.position += self.step;
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            self.previous = self.next;
+            self.next = read_frame(&mut self.stream);
+        }
+        sample
+    }
+}
+
+/// Reads the next frame of a stream, and closes the stream when it's over
+fn read_frame(stream: &mut Option<WaveStream>) -> f32 {
+    match stream.as_mut().map(|stream| stream.next_frame()) {
+        Some(Ok(Some(frame))) => frame,
+        Some(_) => {
+            *stream = None;
+            0.0
+        },
+        None => 0.0,
+    }
+}
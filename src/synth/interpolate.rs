@@ -0,0 +1,77 @@
+// Reading between the samples of a buffer
+
+/// How to read a value that is between two samples
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Just takes the sample before
+    None,
+    Linear,
+    /// Catmull-Rom spline through the two samples on each side
+    Cubic,
+    /// A first order allpass filter. It needs to remember its last output,
+    /// so it only works for things that have somewhere to put that, like
+    /// delays. Everything else reads linearly instead.
+    Allpass,
+}
+
+impl Interpolation {
+    /// The interpolation with the given number, if there is one
+    pub fn from_number(number: usize) -> Option<Interpolation> {
+        match number {
+            0 => Some(Interpolation::None),
+            1 => Some(Interpolation::Linear),
+            2 => Some(Interpolation::Cubic),
+            3 => Some(Interpolation::Allpass),
+            _ => None,
+        }
+    }
+
+    /// Reads from a buffer that loops around, like a wavetable
+    /// or a ring buffer. ``position`` is in samples, and can be
+    /// anywhere from 0 to the length of the buffer.
+    #[inline]
+    pub fn read_wrapping(self, buffer: &[f32], position: f32) -> f32 {
+        let len = buffer.len();
+        if len == 0 {
+            return 0.0;
+        }
+
+        let index = position.floor();
+        let t = position - index;
+        let index = index as usize % len;
+        match self {
+            Interpolation::None => buffer[index],
+            Interpolation::Linear | Interpolation::Allpass => {
+                linear(buffer[index], buffer[(index + 1) % len], t)
+            },
+            Interpolation::Cubic => {
+                cubic(buffer[(index + len - 1) % len], 
+                      buffer[index], 
+                      buffer[(index + 1) % len], 
+                      buffer[(index + 2) % len], 
+                      t)
+            },
+        }
+    }
+}
+
+#[inline]
+pub fn linear(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Interpolates between ``b`` and ``c``
+#[inline]
+pub fn cubic(a: f32, b: f32, c: f32, d: f32, t: f32) -> f32 {
+    let c0 = b;
+    let c1 = 0.5 * (c - a);
+    let c2 = a - 2.5 * b + 2.0 * c - 0.5 * d;
+    let c3 = 0.5 * (d - a) + 1.5 * (b - c);
+    ((c3 * t + c2) * t + c1) * t + c0
+}
+
+/// The coefficient of an allpass filter that delays by ``t`` samples
+#[inline]
+pub fn allpass_coefficient(t: f32) -> f32 {
+    (1.0 - t) / (1.0 + t)
+}
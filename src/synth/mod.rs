@@ -1,9 +1,41 @@
-use crate::operator::Operator;
+use crate::operator::{ Operator, Function };
 use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 
+pub mod analysis;
+pub mod branch;
+mod convolve;
+mod dynamics;
 mod execution_data;
+mod fft;
+mod input;
+mod interpolate;
+mod modulation;
+mod oversample;
+pub mod physical;
+pub mod poly;
+pub mod pattern;
+mod random;
+mod reverb;
+mod sample;
+mod shape;
+pub mod spectral;
+mod stereo;
+pub mod trigger;
+mod tuning;
+mod wavetable;
 pub use execution_data::ExecutionData;
+pub use input::InputFile;
+pub use poly::VoiceSteal;
+pub use tuning::Tuning;
+pub use sample::SampleData;
+pub use wavetable::WavetableData;
+pub use interpolate::Interpolation;
+pub use convolve::ConvolutionData;
+pub use oversample::Oversampler;
+pub use shape::Shape;
+pub use reverb::ReverbLines;
 
 /// The contents of this Id type cannot
 /// ever be equal to "NIL_NODE_ID", because
@@ -13,7 +45,7 @@ pub struct Id(pub u32);
 
 impl Id {
     #[inline]
-    pub fn maybe(self) -> MaybeId {
+    pub(crate) fn maybe(self) -> MaybeId {
         MaybeId(self.0)
     }
 
@@ -23,13 +55,13 @@ impl Id {
     }
 }
 
-const NIL_NODE_ID: u32 = std::u32::MAX;
+const NIL_NODE_ID: u32 = u32::MAX;
 
 /// Just an internal representation to save
 /// space instead of using an option(and not introduce
 /// a null value on Ids)
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
-struct MaybeId(u32);
+pub(crate) struct MaybeId(u32);
 
 impl MaybeId {
     #[inline]
@@ -45,15 +77,6 @@ impl MaybeId {
         }
     }
 
-    #[inline]
-    fn get(self) -> Option<Id> {
-        if self.0 == NIL_NODE_ID {
-            None
-        }else{
-            Some(Id(self.0))
-        }
-    }
-
     #[inline]
     fn get_usize(self) -> Option<usize> {
         if self.0 == NIL_NODE_ID {
@@ -64,8 +87,6 @@ impl MaybeId {
     }
 }
 
-const MAX_INPUTS: usize = 3;
-
 /// TODO: Make this a property of the
 /// datastructure and not something that the user
 /// has to keep in mind on their own.
@@ -81,6 +102,12 @@ pub struct Synth {
     // The nodes in the synth
     nodes: Vec<Node>,
 
+    // The inputs of all the nodes, one after the other.
+    // A node knows where its inputs start, and its kind
+    // knows how many there are.
+    inputs: Vec<MaybeId>,
+    max_inputs: usize,
+
     // The allocations of data from the nodes
     data_allocations: Vec<MaybeId>,
     initial_data: Vec<f32>,
@@ -91,6 +118,11 @@ pub struct Synth {
     probes: HashMap<Id, Probe>,
     probe_id_map: HashMap<Id, Id>,
     probe_id_ctr: u32,
+
+    // Everything random in the synth comes from this
+    pub seed: u32,
+    // How many nodes of each kind have been given a seed
+    seeded_nodes: HashMap<String, u32>,
 }
 
 impl fmt::Debug for Synth {
@@ -103,7 +135,7 @@ impl fmt::Debug for Synth {
 
         writeln!(f, "  Probes:")?;
         for probe in self.probes.iter() {
-            writeln!(f, "    {:?}", probe);
+            writeln!(f, "    {:?}", probe)?;
         }
 
         Ok(())
@@ -114,47 +146,188 @@ impl Synth {
     pub fn new() -> Synth {
         Synth {
             nodes: Vec::new(),
+            inputs: Vec::new(),
+            max_inputs: 0,
             data_allocations: Vec::new(),
             initial_data: Vec::new(),
 
             probes: HashMap::new(),
             probe_id_map: HashMap::new(),
             probe_id_ctr: 0,
+
+            seed: 0,
+            seeded_nodes: HashMap::new(),
         }
     }
 
-    pub fn get_node_data<'a>(&'a self, node: Id) -> Option<&'a [f32]> {
-        if let Some(node) = self.nodes.get(node.0 as usize) {
-            Some(&self.initial_data[node.data_loc.0 as usize..node.data_loc.0 as usize + node.kind.n_data_allocations()])
+    /// A seed for a node of a kind, which is different for every node,
+    /// but always the same for the same synth seed. It only depends on
+    /// how many nodes of that kind got a seed before, so adding other
+    /// nodes to a patch doesn't change it.
+    pub fn node_seed(&mut self, kind: &str) -> u32 {
+        let count = self.seeded_nodes.entry(kind.to_string()).or_insert(0);
+        let kind_seed = kind.bytes().fold(self.seed, |seed, byte| random::hash(seed, byte as u32));
+        let seed = random::hash(kind_seed, *count);
+        *count += 1;
+        seed
+    }
+
+    #[allow(dead_code)]
+    pub fn get_node_data(&self, node: Id) -> Option<&[f32]> {
+        self.nodes.get(node.0 as usize).map(|node| 
+            &self.initial_data[node.data_loc.0 as usize..node.data_loc.0 as usize + node.kind.n_data_allocations()]
+        )
+    }
+
+    /// Gives the location of a piece of data of a node, so that it
+    /// can be used as an input to other nodes.
+    pub fn get_node_data_loc(&self, node: Id, index: usize) -> Option<Id> {
+        let node = self.nodes.get(node.as_usize())?;
+        if index < node.kind.n_data_allocations() {
+            Some(Id(node.data_loc.0 + index as u32))
         }else{
             None
         }
     }
 
-    pub fn get_node<'a>(&'a self, node: Id) -> Option<&'a Node> {
+    #[allow(dead_code)]
+    pub fn get_node(&self, node: Id) -> Option<&Node> {
         self.nodes.get(node.as_usize())
     }
 
+    /// If the id is the first output of a node with named
+    /// outputs, this gives the names and locations of all of them.
+    pub fn get_named_outputs(&self, output: Id) -> Vec<(&'static str, Id)> {
+        let node = match self.data_allocations.get(output.as_usize()).and_then(|v| v.get_usize()) {
+            Some(node) => &self.nodes[node],
+            None => return Vec::new(),
+        };
+
+        if node.get_output_loc(0) != Some(output.as_usize()) {
+            return Vec::new();
+        }
+
+        node.kind.output_names().iter()
+            .enumerate()
+            .map(|(i, &name)| (name, Id(node.get_output_loc(i).unwrap() as u32)))
+            .collect()
+    }
+
     pub fn get_node_output(&self, node: Id) -> Option<Id> {
         self.nodes.get(node.as_usize()).map(|node| Id(node.get_output_loc(0).unwrap() as u32))
     }
 
+    pub fn n_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Like get_node_output, but for nodes with more than one output
+    pub fn get_node_nth_output(&self, node: Id, output: usize) -> Option<Id> {
+        self.nodes.get(node.as_usize())
+            .and_then(|node| node.get_output_loc(output))
+            .map(|loc| Id(loc as u32))
+    }
+
+    /// How many times faster than the synth every node runs, because of oversampling
+    pub fn node_speeds(&self) -> Vec<usize> {
+        let mut speeds = Vec::with_capacity(self.nodes.len());
+        // The ends of the oversampled nodes we're in, and their factors
+        let mut oversampled: Vec<(usize, usize)> = Vec::new();
+        let mut speed = 1;
+        for (i, node) in self.nodes.iter().enumerate() {
+            while let Some(&(end, factor)) = oversampled.last() {
+                if i < end {
+                    break;
+                }
+                oversampled.pop();
+                speed /= factor;
+            }
+            speeds.push(speed);
+
+            if let NodeKind::Oversample(end, factor) = node.kind {
+                oversampled.push((end, factor));
+                speed *= factor;
+            }
+        }
+        speeds
+    }
+
+    /// The files that the ``input`` nodes play into the synth
+    pub fn input_files(&self) -> impl Iterator<Item = &InputFile> {
+        self.nodes.iter().filter_map(|node| match &node.kind {
+            NodeKind::Input(file) => Some(file),
+            _ => None,
+        })
+    }
+
+    /// How many samples late a signal is, because of the latency of the nodes it
+    /// went through. Inputs from nodes added later, like feedback, aren't counted.
+    pub fn latency(&self, output: Id, sample_rate: f32) -> f32 {
+        let speeds = self.node_speeds();
+        let mut latencies: Vec<f32> = Vec::with_capacity(self.nodes.len());
+        for (i, (node, &speed)) in self.nodes.iter().zip(&speeds).enumerate() {
+            let (start, end) = node.get_input_range();
+            let input_latency = self.inputs[start..end].iter()
+                .filter_map(|input| input.get_usize())
+                .filter_map(|input| self.data_allocations[input].get_usize())
+                .filter(|&input_node| input_node < i)
+                .map(|input_node| latencies[input_node])
+                .fold(0.0, f32::max);
+            let speed = speed as f32;
+            latencies.push(input_latency + node.kind.latency(sample_rate * speed) / speed);
+        }
+
+        self.data_allocations.get(output.as_usize())
+            .and_then(|node| node.get_usize())
+            .map_or(0.0, |node| latencies[node])
+    }
+
+    /// Adds a node. If fewer inputs or data than the node kind
+    /// needs are given, the missing inputs are left unconnected
+    /// and the missing data is set to zero.
     pub fn add_node(&mut self, kind: NodeKind, inputs: &[Id], data: &[f32]) -> Id {
         let node_id = Id(self.nodes.len() as u32);
         let alloc_loc = Id(self.initial_data.len() as u32);
-        data.iter().for_each(|&v| self.initial_data.push(v));
-        data.iter().for_each(|_| self.data_allocations.push(node_id.maybe()));
-        // Add the output nodes(only 1 possible so far
-        for _ in 0..1 {
+        for i in 0..kind.n_data_allocations() {
+            self.initial_data.push(data.get(i).copied().unwrap_or(0.0));
+            self.data_allocations.push(node_id.maybe());
+        }
+        // Add the outputs
+        for _ in 0..kind.n_outputs() {
             self.initial_data.push(0.0);
             self.data_allocations.push(node_id.maybe());
         }
-        let mut input_arr = [MaybeId::none(); MAX_INPUTS];
-        inputs.iter().enumerate().for_each(|(i, &v)| input_arr[i] = v.maybe());
-        self.nodes.push(Node { inputs: input_arr, data_loc: alloc_loc, kind: kind });
+        let input_loc = Id(self.inputs.len() as u32);
+        for i in 0..kind.n_inputs() {
+            self.inputs.push(MaybeId::from_option(inputs.get(i).copied()));
+        }
+        self.max_inputs = self.max_inputs.max(kind.n_inputs());
+        self.nodes.push(Node { input_loc, data_loc: alloc_loc, kind });
         node_id
     }
 
+    /// Changes the kind of a node after it has been added, to a kind that has
+    /// the same inputs, data and outputs. This is for nodes that need to know
+    /// about the nodes added after them.
+    pub fn set_node_kind(&mut self, node: Id, kind: NodeKind) {
+        let node = &mut self.nodes[node.as_usize()];
+        assert!(node.kind.n_inputs() == kind.n_inputs() 
+            && node.kind.n_data_allocations() == kind.n_data_allocations()
+            && node.kind.n_outputs() == kind.n_outputs(), 
+            "The new kind has to have the same layout");
+        node.kind = kind;
+    }
+
+    /// Connects an input of a node after it has been added. This
+    /// is useful when a node needs the output of nodes that are
+    /// added after it; it will then read the values those nodes
+    /// had the previous sample.
+    pub fn set_node_input(&mut self, node: Id, input: usize, value: Id) {
+        let node = &self.nodes[node.as_usize()];
+        assert!(input < node.kind.n_inputs(), "Input out of range");
+        self.inputs[node.input_loc.as_usize() + input] = value.maybe();
+    }
+
     pub fn allocate_probe_id(&mut self) -> Id {
         let id = Id(self.probe_id_ctr);
         self.probe_id_ctr += 1;
@@ -162,7 +335,7 @@ impl Synth {
     }
 
     pub fn add_probe(&mut self, id: Id, time: f32, probing: Id) {
-        self.probes.insert(probing, Probe { max_time: time, probing: probing });
+        self.probes.insert(probing, Probe { max_time: time, probing });
         self.probe_id_map.insert(id, probing);
     }
 }
@@ -176,23 +349,28 @@ pub struct Probe {
 
 #[derive(Debug, PartialEq)]
 pub struct Node {
-    inputs: [MaybeId; MAX_INPUTS],
+    input_loc: Id,
     data_loc: Id,
     kind: NodeKind,
 }
 
 impl Node {
+    pub fn get_input_range(&self) -> (usize, usize) {
+        (self.input_loc.as_usize(), self.input_loc.as_usize() + self.kind.n_inputs())
+    }
+
+
     // Nodes are allocated like this: [data, data, data, output, output].
     // Most nodes of course only have 1 output and 0 - 1 piece of data,
     // but this varies from node to node.
     pub fn get_allocated_range(&self) -> (usize, usize) {
-        let size = self.kind.n_data_allocations() + 1; // Right now only 1 output is supported
+        let size = self.kind.n_data_allocations() + self.kind.n_outputs();
         (self.data_loc.0 as usize, self.data_loc.0 as usize + size)
     }
 
     pub fn get_output_loc(&self, output: usize) -> Option<usize> {
         if output < self.kind.n_outputs() {
-            Some(self.data_loc.as_usize() + self.kind.n_data_allocations())
+            Some(self.data_loc.as_usize() + self.kind.n_data_allocations() + output)
         }else{
             None
         }
@@ -203,26 +381,147 @@ impl Node {
 pub enum NodeKind {
     SquareOscillator,
     Oscillator,
+    Wavetable(Rc<WavetableData>, Interpolation),
+    FmOperator,
     Linear(f32),
     Sequence(Vec<f32>),
     Clamp(f32, f32),
     Constant(f32),
     ConstantOp(Operator),
-    Delay(f32, Id), 
+    Function(Function),
+    // The max time, the probe, and how to read between samples
+    Delay(f32, Id, Interpolation),
+    // Attack, decay, sustain and release
+    Adsr(f32, f32, f32, f32),
+    // The number of voices and note streams
+    VoiceAllocator(usize, usize, VoiceSteal),
+    // Ticks per second
+    Clock(f32),
+    // The steps, the gate length and the seed
+    Pattern(Vec<pattern::Step>, f32, u32),
+    MidiToFreq(Rc<Tuning>),
+    FreqToMidi(Rc<Tuning>),
+    // The sample, where to start and where to loop, in samples
+    Sample(Rc<SampleData>, Option<f32>, Option<(f32, f32)>),
+    Reverb(Rc<ReverbLines>),
+    Convolve(Rc<ConvolutionData>),
+    Chorus,
+    Flanger,
+    // The number of allpass stages
+    Phaser(usize),
+    Compressor,
+    Gate,
+    // The lookahead in seconds
+    Limiter(f32),
+    Shaper(Shape, Option<Rc<Oversampler>>),
+    Bitcrush,
+    Decimate,
+    // The node ranges of the branches, and the crossfade time
+    Branch(Vec<(usize, usize)>, f32),
+    // The number of branches
+    BranchOutput(usize),
+    SampleHold,
+    Slew,
+    Lag,
+    RisingEdge,
+    FallingEdge,
+    // The count to wrap around at, or 0 to never wrap
+    Counter(f32),
+    ClockDiv(u32),
+    // Which steps are hits
+    Euclid(Vec<bool>),
+    Pulse,
+    // The seed, and the lowest and highest value
+    Random(u32, f32, f32),
+    Chance(u32),
+    RandomWalk(u32, f32, f32),
+    // The seed and the number of choices
+    Choose(u32, usize),
+    // The seed of the noise
+    Pluck(u32),
+    Modal(Vec<physical::Mode>),
+    // If it follows the rms level instead of the peak level
+    EnvFollow(bool),
+    PitchTrack(analysis::PitchTracker),
+    Pan,
+    Width,
+    // The gains of the inputs
+    Mix(Vec<f32>),
+    // The gains and positions of the inputs
+    StereoMix(Vec<(f32, f32)>),
+    // The output is written by the ExecutionData before every sample
+    Input(InputFile),
+    // The effect and the fft size
+    Spectral(spectral::Effect, usize),
+    // The nodes after this one, up to the first number, are
+    // run the second number of times every sample
+    Oversample(usize, usize),
+    Upsample(Rc<Oversampler>),
+    Downsample(Rc<Oversampler>),
 }
 
 impl NodeKind {
+    #[allow(dead_code)]
     pub fn is_constant(&self) -> bool {
         use NodeKind::*;
         match self {
             SquareOscillator => false,
             Oscillator => false,
+            Wavetable(_, _) => false,
+            FmOperator => false,
             Linear(_) => false,
             Sequence(_) => true,
             Clamp(_, _) => true,
             Constant(_) => true,
             ConstantOp(_) => true,
-            Delay(_, _) => false,
+            Function(_) => true,
+            Delay(_, _, _) => false,
+            Adsr(_, _, _, _) => false,
+            VoiceAllocator(_, _, _) => false,
+            Clock(_) => false,
+            Pattern(_, _, _) => false,
+            MidiToFreq(_) => true,
+            FreqToMidi(_) => true,
+            Sample(_, _, _) => false,
+            Reverb(_) => false,
+            Convolve(_) => false,
+            Chorus => false,
+            Flanger => false,
+            Phaser(_) => false,
+            Compressor => false,
+            Gate => false,
+            Limiter(_) => false,
+            Shaper(_, _) => false,
+            Bitcrush => true,
+            Decimate => false,
+            Branch(_, _) => false,
+            BranchOutput(_) => true,
+            SampleHold => false,
+            Slew => false,
+            Lag => false,
+            RisingEdge => false,
+            FallingEdge => false,
+            Counter(_) => false,
+            ClockDiv(_) => false,
+            Euclid(_) => false,
+            Pulse => false,
+            Random(_, _, _) => false,
+            Chance(_) => false,
+            RandomWalk(_, _, _) => false,
+            Choose(_, _) => false,
+            Pluck(_) => false,
+            Modal(_) => false,
+            EnvFollow(_) => false,
+            PitchTrack(_) => false,
+            Pan => false,
+            Width => false,
+            Mix(_) => false,
+            StereoMix(_) => false,
+            Input(_) => false,
+            Spectral(_, _) => false,
+            Oversample(_, _) => false,
+            Upsample(_) => false,
+            Downsample(_) => false,
         }
     }
 
@@ -232,20 +531,123 @@ impl NodeKind {
         match self {
             SquareOscillator => 1,
             Oscillator => 1,
+            Wavetable(_, _) => 2,
+            FmOperator => 3,
             Linear(_) => 1,
             Sequence(_) => 1,
             Clamp(_, _) => 1,
             Constant(_) => 0,
             ConstantOp(_) => 2,
-            Delay(_, _) => 1,
+            Function(function) => function.n_args(),
+            Delay(_, _, _) => 1,
+            Adsr(_, _, _, _) => 2,
+            VoiceAllocator(voices, streams, _) => streams * poly::STREAM_INPUTS + voices,
+            Clock(_) => 0,
+            Pattern(_, _, _) => 1,
+            MidiToFreq(_) => 1,
+            FreqToMidi(_) => 1,
+            Sample(_, _, _) => 2,
+            Reverb(_) => 5,
+            Convolve(_) => 2,
+            Chorus => 5,
+            Flanger => 5,
+            Phaser(_) => 5,
+            Compressor => 7,
+            Gate => 7,
+            Limiter(_) => 3,
+            Shaper(_, _) => 3,
+            Bitcrush => 2,
+            Decimate => 2,
+            Branch(_, _) => 1,
+            BranchOutput(n_branches) => branch::OUTPUT_STATE_INPUTS + n_branches,
+            SampleHold => 2,
+            Slew => 3,
+            Lag => 2,
+            RisingEdge => 1,
+            FallingEdge => 1,
+            Counter(_) => 2,
+            ClockDiv(_) => 1,
+            Euclid(_) => 1,
+            Pulse => 2,
+            Random(_, _, _) => 1,
+            Chance(_) => 2,
+            RandomWalk(_, _, _) => 2,
+            Choose(_, n_choices) => 1 + n_choices,
+            Pluck(_) => 5,
+            Modal(_) => 2,
+            EnvFollow(_) => 3,
+            PitchTrack(_) => 1,
+            Pan => 2,
+            Width => 3,
+            Mix(gains) => gains.len(),
+            StereoMix(channels) => channels.len(),
+            Input(_) => 0,
+            Spectral(_, _) => 2,
+            Oversample(_, _) => 0,
+            Upsample(_) => 1,
+            Downsample(_) => 1,
         }
     }
 
     #[inline]
     pub fn n_outputs(&self) -> usize {
-        // TODO: Actually make this a proper function
-        // to support more numbers of outputs
-        1
+        match self.output_names().len() {
+            0 => 1,
+            n => n,
+        }
+    }
+
+    /// Nodes with more than one output have names for them, which
+    /// can be accessed as ``$variable.name``. The first one is also
+    /// what you get from just ``$variable``.
+    pub fn output_names(&self) -> &'static [&'static str] {
+        use NodeKind::*;
+        match self {
+            Clock(_) => &["value", "trig", "gate", "phase"],
+            Pattern(_, _, _) => &["freq", "gate", "velocity", "trig"],
+            Euclid(_) => &["trig", "gate"],
+            PitchTrack(_) => &["freq", "clarity"],
+            Pan | Width | StereoMix(_) => &["left", "right"],
+            Reverb(_) => &["left", "right"],
+            _ => &[],
+        }
+    }
+
+    /// How many samples of buffer the node needs at a sample rate. The
+    /// buffer isn't a part of the data, so it's not in the synth, but in
+    /// the ``ExecutionData``, and it starts out as zeroes.
+    pub fn buffer_size(&self, sample_rate: f32) -> usize {
+        use NodeKind::*;
+        match self {
+            Reverb(lines) => lines.buffer_size(),
+            Convolve(conv) => conv.buffer_size(),
+            Chorus => modulation::delay_buffer_size(modulation::CHORUS_DELAY, sample_rate),
+            Flanger => modulation::delay_buffer_size(modulation::FLANGER_DELAY, sample_rate),
+            Limiter(lookahead) => dynamics::limiter_buffer_size(*lookahead, sample_rate),
+            Shaper(_, oversampler) => shape::buffer_size(oversampler),
+            Pluck(_) => physical::pluck_buffer_size(sample_rate),
+            PitchTrack(tracker) => analysis::pitch_track_buffer_size(tracker.min_freq, sample_rate),
+            Spectral(effect, size) => spectral::buffer_size(*effect, *size),
+            Upsample(oversampler) => oversample::up_buffer_size(oversampler),
+            Downsample(oversampler) => oversample::down_buffer_size(oversampler),
+            _ => 0,
+        }
+    }
+
+    /// How many samples late the output of the node is compared to its inputs,
+    /// for nodes that have to wait for more of the input before they can output
+    /// anything. It doesn't count delays that are asked for, like ``Delay``.
+    pub fn latency(&self, sample_rate: f32) -> f32 {
+        use NodeKind::*;
+        match self {
+            Convolve(conv) => conv.block_size as f32,
+            Limiter(lookahead) => dynamics::limiter_length(*lookahead, sample_rate) as f32,
+            Shaper(_, Some(oversampler)) => oversampler.latency(),
+            Spectral(_, size) => *size as f32,
+            // The filters of going up and back down, at the high sample rate
+            Downsample(oversampler) => oversampler.latency() * oversampler.factor as f32,
+            _ => 0.0,
+        }
     }
 
     #[inline]
@@ -254,22 +656,79 @@ impl NodeKind {
         match self {
             SquareOscillator => 1,
             Oscillator => 1,
+            Wavetable(_, _) => wavetable::N_DATA,
+            // The phase, like an oscillator, and the last two outputs for the feedback
+            FmOperator => 3,
             Linear(_) => 1,
             Sequence(_) => 0,
             Clamp(_, _) => 0,
             Constant(_) => 0,
             ConstantOp(_) => 0,
-            Delay(_, _) => 0,
+            Function(_) => 0,
+            // The last output, for allpass interpolation
+            Delay(_, _, _) => 1,
+            Adsr(_, _, _, _) => 4,
+            VoiceAllocator(voices, streams, _) => poly::n_data_allocations(*voices, *streams),
+            Clock(_) => 3,
+            Pattern(_, _, _) => pattern::N_DATA,
+            MidiToFreq(_) => 0,
+            FreqToMidi(_) => 0,
+            Sample(_, _, _) => sample::N_DATA,
+            Reverb(_) => reverb::N_DATA,
+            Convolve(_) => convolve::N_DATA,
+            Chorus => modulation::DELAY_N_DATA,
+            Flanger => modulation::DELAY_N_DATA,
+            Phaser(stages) => modulation::phaser_n_data(*stages),
+            Compressor => dynamics::N_DATA,
+            Gate => dynamics::N_DATA,
+            Limiter(_) => dynamics::LIMITER_N_DATA,
+            Shaper(_, _) => shape::N_DATA,
+            Bitcrush => 0,
+            // The phase and the held value
+            Decimate => 2,
+            Branch(_, _) => branch::N_DATA,
+            BranchOutput(_) => 0,
+            // The held value and the previous trigger
+            SampleHold => 2,
+            Slew => 1,
+            Lag => 1,
+            RisingEdge => 1,
+            FallingEdge => 1,
+            Counter(_) => 3,
+            ClockDiv(_) => 2,
+            Euclid(_) => 3,
+            Pulse => 2,
+            Random(_, _, _) => random::N_DATA,
+            Chance(_) => random::N_DATA,
+            RandomWalk(_, _, _) => random::N_DATA,
+            Choose(_, _) => random::N_DATA,
+            Pluck(_) => physical::PLUCK_N_DATA,
+            Modal(modes) => physical::modal_n_data(modes),
+            EnvFollow(_) => analysis::ENV_FOLLOW_N_DATA,
+            PitchTrack(_) => analysis::PITCH_TRACK_N_DATA,
+            Pan => 0,
+            Width => 0,
+            Mix(_) => 0,
+            StereoMix(_) => 0,
+            Input(_) => 0,
+            Spectral(_, _) => spectral::N_DATA,
+            Oversample(_, _) => 0,
+            Upsample(_) => oversample::N_DATA,
+            Downsample(_) => oversample::N_DATA,
         }
     }
 
     /// We here assume that the inputs and data are of the
     /// correct length, i.e. the values of the "n_inputs" and
     /// "n_data_allocations" functions
+    ///
+    /// # Safety
+    /// The slices have to be of the lengths given by the functions above.
     #[inline]
     pub unsafe fn evaluate(&self, 
-                    get_probe_value: impl Fn(Id, f32) -> Option<f32>, 
+                    get_probe_value: impl Fn(Id, f32, Interpolation) -> Option<f32>, 
                     data: &mut [f32],
+                    buffer: &mut [f32],
                     outputs: &mut [f32],
                     inputs: &[f32], 
                     dt_per_sample: f32) {
@@ -283,6 +742,20 @@ impl NodeKind {
                 data[0] = (data[0] + inputs[0].abs() * dt_per_sample) % 1.0;
                 outputs[0] = (data[0] * 2.0 * std::f32::consts::PI).sin();
             },
+            FmOperator => {
+                // The phase modulation is in radians, and the feedback uses the
+                // average of the last two outputs so that it doesn't oscillate
+                // between two values when it's high.
+                data[0] = (data[0] + inputs[0].abs() * dt_per_sample) % 1.0;
+                let feedback = inputs[2] * (data[1] + data[2]) * 0.5;
+                let output = (data[0] * 2.0 * std::f32::consts::PI + inputs[1] + feedback).sin();
+                data[2] = data[1];
+                data[1] = output;
+                outputs[0] = output;
+            },
+            Wavetable(table, interpolation) => {
+                wavetable::evaluate(table, *interpolation, data, outputs, inputs, dt_per_sample);
+            },
             Linear(max) => {
                 let value = (data[0] + inputs[0] * dt_per_sample) % max;
                 data[0] = value;
@@ -300,9 +773,165 @@ impl NodeKind {
             },
             Constant(c) => outputs[0] = *c,
             ConstantOp(op) => outputs[0] = op.evaluate(inputs[0], inputs[1]),
-            Delay(max, probe) => {
-                let t = inputs[0].max(0.001).min(*max);
-                outputs[0] = get_probe_value(*probe, t).expect("Expected a valid probe");
+            Function(function) => outputs[0] = function.evaluate(inputs),
+            Delay(max, probe, interpolation) => {
+                // The shortest delay is one sample, which is what
+                // a feedback loop without any delay has anyway
+                let t = inputs[0].clamp(dt_per_sample, max.max(dt_per_sample));
+                outputs[0] = match interpolation {
+                    Interpolation::Allpass => {
+                        // The allpass works best delaying by 0.5 to 1.5 samples, and
+                        // at exactly 0 it never settles, so it reads a whole number
+                        // of samples that leaves that much. Half a sample is added
+                        // to not round down to the wrong sample.
+                        let samples = (t / dt_per_sample).max(1.5);
+                        let whole = (samples - 0.5).floor();
+                        let fraction = samples - whole;
+                        let time = (whole + 0.5) * dt_per_sample;
+                        let newer = get_probe_value(*probe, time, Interpolation::None).expect("Expected a valid probe");
+                        let older = get_probe_value(*probe, time + dt_per_sample, Interpolation::None).unwrap_or(newer);
+                        let c = interpolate::allpass_coefficient(fraction);
+                        data[0] = c * newer + older - c * data[0];
+                        data[0]
+                    },
+                    _ => get_probe_value(*probe, t, *interpolation).expect("Expected a valid probe"),
+                };
+            },
+            Adsr(attack, decay, sustain, release) => {
+                // The data is [level, stage, previous gate, previous trigger], where the
+                // stage is 0 for off, 1 for attack, 2 for decay/sustain and 3 for release.
+                let gate = inputs[0] > 0.0;
+                if gate && (data[2] <= 0.0 || trigger::is_rising(inputs[1], data[3])) {
+                    data[1] = 1.0;
+                }else if !gate && data[2] > 0.0 {
+                    data[1] = 3.0;
+                }
+                data[2] = inputs[0];
+                data[3] = inputs[1];
+
+                // Decay and release are the times it takes to fall by 60db
+                let level = data[0];
+                data[0] = match data[1] as u32 {
+                    1 => {
+                        let level = level + dt_per_sample / attack.max(dt_per_sample);
+                        if level >= 1.0 {
+                            data[1] = 2.0;
+                        }
+                        level.min(1.0)
+                    },
+                    2 => sustain + (level - sustain) * (-6.9 * dt_per_sample / decay.max(dt_per_sample)).exp(),
+                    3 => {
+                        let level = level * (-6.9 * dt_per_sample / release.max(dt_per_sample)).exp();
+                        if level < 0.0001 {
+                            data[1] = 0.0;
+                            0.0
+                        }else{
+                            level
+                        }
+                    },
+                    _ => 0.0,
+                };
+                outputs[0] = data[0];
+            },
+            VoiceAllocator(_, streams, steal) => {
+                poly::evaluate(*steal, *streams, data, outputs, inputs, dt_per_sample);
+            },
+            Clock(rate) => {
+                // The data is [phase, ticks, started]. The phase and the
+                // number of ticks are kept apart so that the phase doesn't
+                // lose precision as time goes on.
+                let mut trigger = data[2] == 0.0;
+                data[2] = 1.0;
+                if !trigger {
+                    data[0] += rate * dt_per_sample;
+                    if data[0] >= 1.0 {
+                        let ticks = data[0].floor();
+                        data[0] -= ticks;
+                        data[1] += ticks;
+                        trigger = true;
+                    }
+                }
+
+                outputs[0] = data[1] + data[0];
+                outputs[1] = if trigger { 1.0 } else { 0.0 };
+                outputs[2] = if data[0] < 0.5 { 1.0 } else { 0.0 };
+                outputs[3] = data[0];
+            },
+            Pattern(steps, gate_length, seed) => {
+                pattern::evaluate(steps, *gate_length, *seed, data, outputs, inputs, dt_per_sample);
+            },
+            MidiToFreq(tuning) => outputs[0] = tuning.midi_to_freq(inputs[0]),
+            FreqToMidi(tuning) => outputs[0] = tuning.freq_to_midi(inputs[0]),
+            Sample(sample, start, looping) => {
+                sample::evaluate(sample, *start, *looping, data, outputs, inputs, dt_per_sample);
+            },
+            Reverb(lines) => reverb::evaluate(lines, data, buffer, outputs, inputs, dt_per_sample),
+            Convolve(conv) => convolve::evaluate(conv, data, buffer, outputs, inputs),
+            Chorus => {
+                modulation::evaluate_delay(modulation::CHORUS_DELAY, 2, data, buffer, outputs, inputs, dt_per_sample);
+            },
+            Flanger => {
+                modulation::evaluate_delay(modulation::FLANGER_DELAY, 1, data, buffer, outputs, inputs, dt_per_sample);
+            },
+            Phaser(stages) => modulation::evaluate_phaser(*stages, data, outputs, inputs, dt_per_sample),
+            Compressor => dynamics::evaluate_compressor(data, outputs, inputs, dt_per_sample),
+            Gate => dynamics::evaluate_gate(data, outputs, inputs, dt_per_sample),
+            Limiter(lookahead) => {
+                dynamics::evaluate_limiter(*lookahead, data, buffer, outputs, inputs, dt_per_sample);
+            },
+            Shaper(shape, oversampler) => shape::evaluate(*shape, oversampler, data, buffer, outputs, inputs),
+            Bitcrush => shape::bitcrush(outputs, inputs),
+            Decimate => shape::decimate(data, outputs, inputs, dt_per_sample),
+            Branch(ranges, fade_time) => branch::evaluate(ranges.len(), *fade_time, data, inputs, dt_per_sample),
+            BranchOutput(_) => branch::evaluate_output(outputs, inputs),
+            SampleHold => {
+                if inputs[0] > 0.0 && data[1] <= 0.0 {
+                    data[0] = inputs[1];
+                }
+                data[1] = inputs[0];
+                outputs[0] = data[0];
+            },
+            Slew => {
+                // The inputs are the signal, and the times it takes to
+                // go up and down by 1
+                let diff = inputs[0] - data[0];
+                let max_step = if diff > 0.0 { 
+                    dt_per_sample / inputs[1].max(0.0) 
+                }else{ 
+                    dt_per_sample / inputs[2].max(0.0) 
+                };
+                data[0] += diff.clamp(-max_step, max_step);
+                outputs[0] = data[0];
+            },
+            RisingEdge => trigger::rising_edge(data, outputs, inputs),
+            FallingEdge => trigger::falling_edge(data, outputs, inputs),
+            Counter(max) => trigger::counter(*max, data, outputs, inputs),
+            ClockDiv(n) => trigger::clock_div(*n, data, outputs, inputs),
+            Euclid(pattern) => trigger::euclid(pattern, data, outputs, inputs),
+            Pulse => trigger::pulse(data, outputs, inputs, dt_per_sample),
+            Random(seed, min, max) => random::random(*seed, *min, *max, data, outputs, inputs),
+            Chance(seed) => random::chance(*seed, data, outputs, inputs),
+            RandomWalk(seed, min, max) => random::random_walk(*seed, *min, *max, data, outputs, inputs),
+            Choose(seed, _) => random::choose(*seed, data, outputs, inputs),
+            Pluck(seed) => physical::pluck(*seed, data, buffer, outputs, inputs, dt_per_sample),
+            Modal(modes) => physical::modal(modes, data, outputs, inputs, dt_per_sample),
+            EnvFollow(rms) => analysis::env_follow(*rms, data, outputs, inputs, dt_per_sample),
+            PitchTrack(tracker) => analysis::pitch_track(tracker, data, buffer, outputs, inputs, dt_per_sample),
+            Pan => stereo::pan(outputs, inputs),
+            Width => stereo::width(outputs, inputs),
+            Mix(gains) => stereo::mix(gains, outputs, inputs),
+            StereoMix(channels) => stereo::stereo_mix(channels, outputs, inputs),
+            Input(_) => (),
+            Spectral(effect, size) => spectral::evaluate(*effect, *size, data, buffer, outputs, inputs),
+            // The ExecutionData runs the oversampled nodes
+            Oversample(_, _) => (),
+            Upsample(oversampler) => oversample::evaluate_up(oversampler, data, buffer, outputs, inputs),
+            Downsample(oversampler) => oversample::evaluate_down(oversampler, data, buffer, outputs, inputs),
+            Lag => {
+                // A one pole lowpass filter, which gets about 63% of the
+                // way to the signal in the time given by the second input
+                data[0] += (inputs[0] - data[0]) * dynamics::smoothing(inputs[1], dt_per_sample);
+                outputs[0] = data[0];
             },
         }
     }
@@ -0,0 +1,92 @@
+// Modulation effects: chorus, flanger and phaser.
+//
+// Chorus and flanger are both a delay line whose length is moved around by
+// a sin wave, they just have different lengths. The data for them is
+// [write position, lfo phase], and the buffer is the delay line. The
+// phaser is a chain of allpass filters whose frequency is moved around by
+// a sin wave, and doesn't need a buffer. Its data is
+// [lfo phase, last output, (last input, last output) for every stage].
+//
+// The inputs are the signal, the rate of the sin wave, the depth,
+// the feedback and the wet/dry mix.
+
+use std::f32::consts::PI;
+use super::interpolate::Interpolation;
+
+/// Delay line lengths in seconds, the shortest one and how much longer
+/// it gets at full depth
+pub const CHORUS_DELAY: (f32, f32) = (0.007, 0.02);
+pub const FLANGER_DELAY: (f32, f32) = (0.0005, 0.005);
+
+pub const DELAY_N_DATA: usize = 2;
+
+/// The lowest frequency of the phaser, and how many octaves higher
+/// it goes at full depth
+const PHASER_MIN_FREQ: f32 = 200.0;
+const PHASER_OCTAVES: f32 = 5.0;
+
+pub fn phaser_n_data(stages: usize) -> usize {
+    2 + 2 * stages
+}
+
+pub fn delay_buffer_size(delay: (f32, f32), sample_rate: f32) -> usize {
+    ((delay.0 + delay.1) * sample_rate).ceil() as usize + 2
+}
+
+/// Evaluates a chorus or a flanger. The chorus has two taps, with the
+/// sin waves half a cycle apart, which makes it sound fuller.
+pub fn evaluate_delay(delay: (f32, f32),
+                      taps: usize,
+                      data: &mut [f32],
+                      buffer: &mut [f32],
+                      outputs: &mut [f32],
+                      inputs: &[f32],
+                      dt_per_sample: f32) {
+    let len = buffer.len();
+    let write = data[0] as usize % len;
+    data[1] = (data[1] + inputs[1].abs() * dt_per_sample) % 1.0;
+    let lfo_phase = data[1];
+    let depth = inputs[2].clamp(0.0, 1.0);
+    let feedback = inputs[3].clamp(-0.99, 0.99);
+    let mix = inputs[4].clamp(0.0, 1.0);
+
+    let mut wet = 0.0;
+    for tap in 0..taps {
+        let lfo = ((lfo_phase + tap as f32 / taps as f32) * 2.0 * PI).sin();
+        let time = delay.0 + delay.1 * depth * (0.5 + 0.5 * lfo);
+        let samples = (time / dt_per_sample).max(1.0);
+        wet += Interpolation::Linear.read_wrapping(buffer, write as f32 + len as f32 - samples);
+    }
+    wet /= taps as f32;
+
+    buffer[write] = inputs[0] + wet * feedback;
+    data[0] = ((write + 1) % len) as f32;
+    outputs[0] = inputs[0] * (1.0 - mix) + wet * mix;
+}
+
+pub fn evaluate_phaser(stages: usize,
+                       data: &mut [f32],
+                       outputs: &mut [f32],
+                       inputs: &[f32],
+                       dt_per_sample: f32) {
+    data[0] = (data[0] + inputs[1].abs() * dt_per_sample) % 1.0;
+    let lfo = (data[0] * 2.0 * PI).sin();
+    let depth = inputs[2].clamp(0.0, 1.0);
+    let feedback = inputs[3].clamp(-0.99, 0.99);
+    let mix = inputs[4].clamp(0.0, 1.0);
+
+    let freq = PHASER_MIN_FREQ * 2f32.powf(PHASER_OCTAVES * depth * (0.5 + 0.5 * lfo));
+    let tan = (PI * (freq * dt_per_sample).min(0.49)).tan();
+    let a = (tan - 1.0) / (tan + 1.0);
+
+    let mut wet = inputs[0] + data[1] * feedback;
+    for stage in data[2..2 + 2 * stages].chunks_exact_mut(2) {
+        let output = a * wet + stage[0] - a * stage[1];
+        stage[0] = wet;
+        stage[1] = output;
+        wet = output;
+    }
+    data[1] = wet;
+
+    outputs[0] = inputs[0] * (1.0 - mix) + wet * mix;
+}
@@ -0,0 +1,176 @@
+// Changing the sample rate by a whole number factor, to run things that
+// alias at a higher sample rate.
+//
+// Both ways use the same Kaiser windowed sinc lowpass filter, which is
+// flat up to 20khz at 48khz and cuts everything above half the low sample
+// rate by 80db, so that it neither dulls the sound nor lets the aliasing
+// through. It's long, so it costs some cpu and latency. Going up, the
+// samples are spread out with zeroes in between and then filtered, which
+// is done without the zeroes by only using every ``factor``th tap. Going
+// down, the samples are filtered and then all but every ``factor``th are
+// thrown away, so only those are filtered.
+//
+// Oversampled parts of the synth are run ``factor`` times every sample,
+// and start with an upsampling node for every input and end with a
+// downsampling node. Those count how many times they have been run, so
+// the upsampling node makes all its samples the first time and gives
+// one at a time, and the downsampling node gathers samples until it has
+// ``factor`` of them. Their data is [position in the history, count], and
+// their buffers are [history, samples at the high sample rate].
+
+use std::f64::consts::PI;
+
+/// How many taps the filter has for each sample at the low sample rate
+const TAPS_PER_PHASE: usize = 64;
+
+/// Where the filter starts and stops cutting, compared to half the low sample rate
+const PASS: f64 = 20.0 / 24.0;
+const STOP: f64 = 1.0;
+
+/// How much the filter cuts above ``STOP``, in db
+const ATTENUATION: f64 = 80.0;
+
+pub const N_DATA: usize = 2;
+
+#[derive(PartialEq)]
+pub struct Oversampler {
+    pub factor: usize,
+    taps: Vec<f32>,
+}
+
+impl std::fmt::Debug for Oversampler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Oversampler ( factor: {} )", self.factor)
+    }
+}
+
+impl Oversampler {
+    pub fn new(factor: usize) -> Oversampler {
+        let factor = factor.max(1);
+        let n_taps = factor * TAPS_PER_PHASE;
+        // The cutoff is in the middle of the band where the filter cuts
+        let cutoff = (PASS + STOP) / 2.0 * 0.5 / factor as f64;
+        let middle = (n_taps - 1) as f64 / 2.0;
+        let beta = 0.1102 * (ATTENUATION - 8.7);
+
+        let taps: Vec<f64> = (0..n_taps).map(|i| {
+            let x = i as f64 - middle;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            }else{
+                (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+            let t = x / middle;
+            let window = bessel_i0(beta * (1.0 - t * t).max(0.0).sqrt()) / bessel_i0(beta);
+            sinc * window
+        }).collect();
+
+        // Make it not change the volume of low frequencies
+        let sum: f64 = taps.iter().sum();
+        let taps = taps.iter().map(|v| (v / sum) as f32).collect();
+
+        Oversampler { factor, taps }
+    }
+
+    /// How many samples at the low sample rate going up and back down delays by.
+    /// Both filters delay by half their length, but going down keeps the last of
+    /// every ``factor`` samples, which is ``factor - 1`` samples later.
+    pub fn latency(&self) -> f32 {
+        (self.taps.len() - self.factor) as f32 / self.factor as f32
+    }
+
+    /// How much history upsampling needs
+    pub fn up_size(&self) -> usize {
+        TAPS_PER_PHASE
+    }
+
+    /// How much history downsampling needs
+    pub fn down_size(&self) -> usize {
+        self.taps.len()
+    }
+
+    /// Takes one sample, and puts ``factor`` samples at the high sample rate in the output
+    pub fn upsample(&self, history: &mut [f32], position: &mut f32, input: f32, output: &mut [f32]) {
+        let len = history.len();
+        let newest = *position as usize % len;
+        history[newest] = input;
+        *position = ((newest + 1) % len) as f32;
+
+        for (phase, output) in output.iter_mut().enumerate().take(self.factor) {
+            let mut sum = 0.0;
+            for j in 0..TAPS_PER_PHASE {
+                sum += self.taps[phase + j * self.factor] * history[(newest + len - j) % len];
+            }
+            // Every sample was spread out over ``factor`` samples, so it has to be louder
+            *output = sum * self.factor as f32;
+        }
+    }
+
+    /// Takes ``factor`` samples at the high sample rate and gives one sample
+    pub fn downsample(&self, history: &mut [f32], position: &mut f32, input: &[f32]) -> f32 {
+        let len = history.len();
+        let mut newest = *position as usize;
+        for &value in &input[..self.factor] {
+            newest = (newest + 1) % len;
+            history[newest] = value;
+        }
+        *position = newest as f32;
+
+        self.taps.iter().enumerate()
+            .map(|(k, tap)| tap * history[(newest + len - k) % len])
+            .sum()
+    }
+}
+
+/// The modified bessel function of the first kind, which the Kaiser window is made of
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+pub fn up_buffer_size(oversampler: &Oversampler) -> usize {
+    oversampler.up_size() + oversampler.factor
+}
+
+pub fn down_buffer_size(oversampler: &Oversampler) -> usize {
+    oversampler.down_size() + oversampler.factor
+}
+
+/// The input is at the low sample rate, and the output at the high one
+pub fn evaluate_up(oversampler: &Oversampler,
+                   data: &mut [f32],
+                   buffer: &mut [f32],
+                   outputs: &mut [f32],
+                   inputs: &[f32]) {
+    let (history, samples) = buffer.split_at_mut(oversampler.up_size());
+    let (position, count) = data.split_at_mut(1);
+    let n = count[0] as usize % oversampler.factor;
+    if n == 0 {
+        oversampler.upsample(history, &mut position[0], inputs[0], samples);
+    }
+    outputs[0] = samples[n];
+    count[0] = ((n + 1) % oversampler.factor) as f32;
+}
+
+/// The input is at the high sample rate, and the output at the low one
+pub fn evaluate_down(oversampler: &Oversampler,
+                     data: &mut [f32],
+                     buffer: &mut [f32],
+                     outputs: &mut [f32],
+                     inputs: &[f32]) {
+    let (history, samples) = buffer.split_at_mut(oversampler.down_size());
+    let (position, count) = data.split_at_mut(1);
+    let n = count[0] as usize % oversampler.factor;
+    samples[n] = inputs[0];
+    if n + 1 == oversampler.factor {
+        outputs[0] = oversampler.downsample(history, &mut position[0], samples);
+    }
+    count[0] = ((n + 1) % oversampler.factor) as f32;
+}
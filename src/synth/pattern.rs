@@ -0,0 +1,89 @@
+// The node behind the "pattern" function, which steps through
+// a list of notes every time its input is triggered.
+//
+// The data of a pattern looks like this:
+// [next step, previous trigger, time since the step started,
+//  length of the last step, playing, freq, velocity]
+
+use super::random;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum StepKind {
+    Note(f32),
+    Hit,
+    Rest,
+    Tie,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Step {
+    pub kind: StepKind,
+    pub velocity: f32,
+    pub probability: f32,
+}
+
+pub const N_DATA: usize = 7;
+
+const NEXT_STEP: usize = 0;
+const PREV_TRIGGER: usize = 1;
+const TIME: usize = 2;
+const STEP_LENGTH: usize = 3;
+const PLAYING: usize = 4;
+const FREQ: usize = 5;
+const VELOCITY: usize = 6;
+
+/// The gate length is the part of a step that the gate is high for,
+/// the seed is for deciding what steps to play when they have a probability.
+pub fn evaluate(steps: &[Step],
+                gate_length: f32,
+                seed: u32,
+                data: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32],
+                dt_per_sample: f32) {
+    let trigger = inputs[0] > 0.0 && data[PREV_TRIGGER] <= 0.0;
+    data[PREV_TRIGGER] = inputs[0];
+    data[TIME] += dt_per_sample;
+
+    let mut note_start = false;
+    if trigger {
+        // We don't know how long steps are until we've seen two of them
+        if data[NEXT_STEP] > 0.0 {
+            data[STEP_LENGTH] = data[TIME];
+        }
+        data[TIME] = 0.0;
+
+        let index = data[NEXT_STEP] as usize;
+        data[NEXT_STEP] += 1.0;
+
+        let step = steps[index % steps.len()];
+        match step.kind {
+            StepKind::Rest => data[PLAYING] = 0.0,
+            StepKind::Tie => (),
+            StepKind::Note(_) | StepKind::Hit => {
+                if step.probability >= 1.0 || random::hash_to_unit(seed, index as u32) < step.probability {
+                    if let StepKind::Note(freq) = step.kind {
+                        data[FREQ] = freq;
+                    }
+                    data[VELOCITY] = step.velocity;
+                    data[PLAYING] = 1.0;
+                    note_start = true;
+                }else{
+                    data[PLAYING] = 0.0;
+                }
+            },
+        }
+    }
+
+    // The gate is held through the next step if it's a tie. When
+    // a new note starts the gate goes low for a sample, so that
+    // whatever uses it can see that a new note started.
+    let next_is_tie = steps[data[NEXT_STEP] as usize % steps.len()].kind == StepKind::Tie;
+    let held = data[STEP_LENGTH] == 0.0 || next_is_tie || data[TIME] < gate_length * data[STEP_LENGTH];
+    let gate = data[PLAYING] > 0.0 && held && !note_start;
+
+    outputs[0] = data[FREQ];
+    outputs[1] = if gate { 1.0 } else { 0.0 };
+    outputs[2] = data[VELOCITY];
+    outputs[3] = if note_start { 1.0 } else { 0.0 };
+}
@@ -0,0 +1,127 @@
+// Physical modelling: plucked strings and banks of resonators.
+//
+// The pluck is a Karplus-Strong string, which is a delay line as long as
+// one period of the note, that feeds back into itself through a lowpass
+// filter. The length of the delay line is tuned between samples with an
+// allpass filter. It's plucked by adding a burst of noise to it. The buffer
+// is the delay line, and the data is [write position, allpass input, 
+// allpass output, previous trigger, noise left to add, noise filter, 
+// number of plucks].
+//
+// The resonators are two pole filters that ring at a frequency, and the
+// data is the last two outputs of every resonator.
+
+use std::f32::consts::PI;
+use super::random;
+
+/// The lowest note the pluck can play
+const PLUCK_MIN_FREQ: f32 = 20.0;
+
+pub const PLUCK_N_DATA: usize = 7;
+
+const WRITE: usize = 0;
+const ALLPASS_IN: usize = 1;
+const ALLPASS_OUT: usize = 2;
+const PREV_TRIGGER: usize = 3;
+const NOISE_LEFT: usize = 4;
+const NOISE_FILTER: usize = 5;
+const PLUCKS: usize = 6;
+
+pub fn pluck_buffer_size(sample_rate: f32) -> usize {
+    (sample_rate / PLUCK_MIN_FREQ).ceil() as usize + 2
+}
+
+/// The inputs are the trigger, the frequency, the time it takes to get 60db
+/// quieter, how much the high frequencies are damped and how bright the pluck is.
+/// The last three are from 0 to 1.
+pub fn pluck(seed: u32,
+             data: &mut [f32],
+             buffer: &mut [f32],
+             outputs: &mut [f32],
+             inputs: &[f32],
+             dt_per_sample: f32) {
+    let len = buffer.len();
+    let sample_rate = 1.0 / dt_per_sample;
+    let period = (sample_rate / inputs[1].max(PLUCK_MIN_FREQ)).min((len - 2) as f32);
+
+    // The loop filter is (1 - s) + s * z^-1, which delays by s samples
+    let s = inputs[3].clamp(0.0, 1.0) * 0.5;
+    let delay = (period - s).max(1.5);
+
+    // The allpass works best delaying by 0.5 to 1.5 samples
+    let whole = (delay - 0.5).floor();
+    let fraction = delay - whole;
+    let c = (1.0 - fraction) / (1.0 + fraction);
+
+    let write = data[WRITE] as usize % len;
+    let read = buffer[(write + len - whole as usize) % len];
+    let allpass = c * read + data[ALLPASS_IN] - c * data[ALLPASS_OUT];
+    let filtered = (1.0 - s) * allpass + s * data[ALLPASS_OUT];
+    data[ALLPASS_IN] = read;
+    data[ALLPASS_OUT] = allpass;
+
+    // How much is left after going around the loop once
+    let decay = inputs[2].max(dt_per_sample);
+    let gain = 0.001f32.powf(1.0 / (inputs[1].max(PLUCK_MIN_FREQ) * decay));
+
+    if inputs[0] > 0.0 && data[PREV_TRIGGER] <= 0.0 {
+        data[NOISE_LEFT] = period;
+        data[PLUCKS] += 1.0;
+    }
+    data[PREV_TRIGGER] = inputs[0];
+
+    let mut excitation = 0.0;
+    if data[NOISE_LEFT] > 0.0 {
+        data[NOISE_LEFT] -= 1.0;
+        let noise_index = (data[PLUCKS] as u32).wrapping_mul(1 << 16).wrapping_add(data[NOISE_LEFT] as u32);
+        let noise = random::hash_to_unit(seed, noise_index) * 2.0 - 1.0;
+        // Darker plucks have their noise lowpass filtered
+        let brightness = inputs[4].clamp(0.0, 1.0);
+        data[NOISE_FILTER] += (noise - data[NOISE_FILTER]) * (brightness * brightness).max(0.01);
+        excitation = data[NOISE_FILTER];
+    }
+
+    let output = filtered * gain + excitation;
+    buffer[write] = output;
+    data[WRITE] = ((write + 1) % len) as f32;
+    outputs[0] = output;
+}
+
+/// A mode of a resonator bank, with a frequency compared to
+/// the pitch of the bank, a decay time and a gain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mode {
+    pub ratio: f32,
+    pub decay: f32,
+    pub gain: f32,
+}
+
+pub fn modal_n_data(modes: &[Mode]) -> usize {
+    2 * modes.len()
+}
+
+/// The inputs are the signal to excite the resonators with, and
+/// the pitch, which the ratios of the modes are multiplied by
+pub fn modal(modes: &[Mode],
+             data: &mut [f32],
+             outputs: &mut [f32],
+             inputs: &[f32],
+             dt_per_sample: f32) {
+    let mut output = 0.0;
+    for (mode, state) in modes.iter().zip(data.chunks_exact_mut(2)) {
+        let freq = mode.ratio * inputs[1];
+        let w = 2.0 * PI * freq * dt_per_sample;
+        if w <= 0.0 || w >= PI {
+            // It can't ring above half the sample rate
+            continue;
+        }
+
+        let r = 0.001f32.powf(dt_per_sample / mode.decay.max(dt_per_sample));
+        // The sin makes an impulse ring with an amplitude of about the gain
+        let y = mode.gain * w.sin() * inputs[0] + 2.0 * r * w.cos() * state[0] - r * r * state[1];
+        state[1] = state[0];
+        state[0] = y;
+        output += y;
+    }
+    outputs[0] = output;
+}
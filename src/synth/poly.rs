@@ -0,0 +1,147 @@
+// The voice allocator behind the "poly" function.
+//
+// Notes come in as "streams", where every stream is a gate, a
+// frequency and a velocity. When the gate of a stream goes high
+// a voice is given the note, and when it goes low again the voice
+// is released. Every voice is a copy of the same sub graph, which
+// reads its frequency, gate, velocity and trigger straight from the
+// data of the allocator.
+//
+// The data of the allocator looks like this:
+// [note counter, stream, stream, ..., voice, voice, ...],
+// where a stream is [previous gate, voice, note]
+// and a voice is [freq, gate, velocity, trigger, note, level].
+
+pub const STREAM_INPUTS: usize = 3;
+const STREAM_DATA: usize = 3;
+const VOICE_DATA: usize = 6;
+
+const STREAM_PREV_GATE: usize = 0;
+const STREAM_VOICE: usize = 1;
+const STREAM_NOTE: usize = 2;
+
+pub const VOICE_FREQ: usize = 0;
+pub const VOICE_GATE: usize = 1;
+pub const VOICE_VELOCITY: usize = 2;
+pub const VOICE_TRIGGER: usize = 3;
+const VOICE_NOTE: usize = 4;
+const VOICE_LEVEL: usize = 5;
+
+/// How long it takes for the level of a voice to fall
+/// by a factor of e when it stops making sound
+const LEVEL_TIME: f32 = 0.05;
+/// When a released voice is quieter than this it is free to use
+const QUIET_LEVEL: f32 = 0.0005;
+
+/// How to choose what voice to take when all of them are in use
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum VoiceSteal {
+    Oldest,
+    Quietest,
+}
+
+pub fn n_data_allocations(voices: usize, streams: usize) -> usize {
+    1 + streams * STREAM_DATA + voices * VOICE_DATA
+}
+
+/// The initial data of an allocator. Streams start out
+/// not owning any voice.
+pub fn initial_data(voices: usize, streams: usize) -> Vec<f32> {
+    let mut data = vec![0.0; n_data_allocations(voices, streams)];
+    for stream in data[1..1 + streams * STREAM_DATA].chunks_mut(STREAM_DATA) {
+        stream[STREAM_VOICE] = -1.0;
+    }
+    data
+}
+
+/// Where in the data of the allocator a parameter of a voice is,
+/// the parameter being one of the VOICE_ constants
+pub fn voice_data_loc(streams: usize, voice: usize, parameter: usize) -> usize {
+    1 + streams * STREAM_DATA + voice * VOICE_DATA + parameter
+}
+
+/// The inputs are [gate, freq, velocity] for every stream, followed
+/// by the outputs of every voice(used to know how loud they are).
+pub fn evaluate(steal: VoiceSteal,
+                streams: usize,
+                data: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32],
+                dt_per_sample: f32) {
+    let (counter, data) = data.split_at_mut(1);
+    let (stream_data, voice_data) = data.split_at_mut(streams * STREAM_DATA);
+    let voice_outputs = &inputs[streams * STREAM_INPUTS..];
+
+    let decay = (-dt_per_sample / LEVEL_TIME).exp();
+    for (voice, &output) in voice_data.chunks_mut(VOICE_DATA).zip(voice_outputs) {
+        voice[VOICE_TRIGGER] = 0.0;
+        voice[VOICE_LEVEL] = output.abs().max(voice[VOICE_LEVEL] * decay);
+    }
+
+    for (stream, input) in stream_data.chunks_mut(STREAM_DATA).zip(inputs.chunks(STREAM_INPUTS)) {
+        let (gate, freq, velocity) = (input[0], input[1], input[2]);
+        let prev_gate = stream[STREAM_PREV_GATE];
+        stream[STREAM_PREV_GATE] = gate;
+
+        if gate > 0.0 && prev_gate <= 0.0 {
+            counter[0] += 1.0;
+            let index = pick_voice(voice_data, steal);
+            let voice = &mut voice_data[index * VOICE_DATA..(index + 1) * VOICE_DATA];
+            voice[VOICE_FREQ] = freq;
+            voice[VOICE_GATE] = 1.0;
+            voice[VOICE_VELOCITY] = velocity;
+            voice[VOICE_TRIGGER] = 1.0;
+            voice[VOICE_NOTE] = counter[0];
+
+            stream[STREAM_VOICE] = index as f32;
+            stream[STREAM_NOTE] = counter[0];
+        }else if stream[STREAM_VOICE] >= 0.0 {
+            let index = stream[STREAM_VOICE] as usize;
+            let voice = &mut voice_data[index * VOICE_DATA..(index + 1) * VOICE_DATA];
+
+            // The voice may have been stolen by another stream
+            if voice[VOICE_NOTE] != stream[STREAM_NOTE] {
+                stream[STREAM_VOICE] = -1.0;
+            }else if gate <= 0.0 {
+                voice[VOICE_GATE] = 0.0;
+                stream[STREAM_VOICE] = -1.0;
+            }else{
+                voice[VOICE_FREQ] = freq;
+            }
+        }
+    }
+
+    // Output the number of voices making sound
+    outputs[0] = voice_data.chunks(VOICE_DATA)
+        .filter(|voice| !is_free(voice))
+        .count() as f32;
+}
+
+fn is_free(voice: &[f32]) -> bool {
+    voice[VOICE_GATE] <= 0.0 && voice[VOICE_LEVEL] < QUIET_LEVEL
+}
+
+fn pick_voice(voice_data: &[f32], steal: VoiceSteal) -> usize {
+    let voices = || voice_data.chunks(VOICE_DATA).enumerate();
+
+    // Take the free voice that was used the longest time ago
+    if let Some((index, _)) = voices()
+            .filter(|(_, voice)| is_free(voice))
+            .min_by(|(_, a), (_, b)| a[VOICE_NOTE].total_cmp(&b[VOICE_NOTE])) {
+        return index;
+    }
+
+    // There were no free voices, so we have to steal one. Voices that
+    // have been released are stolen before voices that are still held.
+    let any_released = voices().any(|(_, voice)| voice[VOICE_GATE] <= 0.0);
+    let parameter = match steal {
+        VoiceSteal::Oldest => VOICE_NOTE,
+        VoiceSteal::Quietest => VOICE_LEVEL,
+    };
+
+    voices()
+        .filter(|(_, voice)| !any_released || voice[VOICE_GATE] <= 0.0)
+        .min_by(|(_, a), (_, b)| a[parameter].total_cmp(&b[parameter]))
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
@@ -0,0 +1,78 @@
+// Randomness in the synth has to be the same every time a
+// file is played, so instead of keeping some random state
+// around, random numbers come from hashing where we are.
+//
+// The random nodes count the triggers they get, and hash the
+// count with their seed. Their data starts with
+// [previous trigger, number of triggers].
+
+/// Mixes a seed and a number into a random looking number
+pub fn hash(seed: u32, value: u32) -> u32 {
+    let mut x = seed ^ value.wrapping_mul(0x9e37_79b9);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+/// Turns a seed and a number into a random looking number from 0 to 1
+pub fn hash_to_unit(seed: u32, value: u32) -> f32 {
+    (hash(seed, value) >> 8) as f32 / (1u32 << 24) as f32
+}
+
+pub const N_DATA: usize = 3;
+
+const PREV_TRIGGER: usize = 0;
+const COUNT: usize = 1;
+const VALUE: usize = 2;
+
+/// If the trigger went above 0, gives a new random number
+#[inline]
+fn next(seed: u32, data: &mut [f32], trigger: f32) -> Option<f32> {
+    let triggered = trigger > 0.0 && data[PREV_TRIGGER] <= 0.0;
+    data[PREV_TRIGGER] = trigger;
+    if triggered {
+        let value = hash_to_unit(seed, data[COUNT] as u32);
+        data[COUNT] += 1.0;
+        Some(value)
+    }else{
+        None
+    }
+}
+
+/// A new value from ``min`` to ``max`` for every trigger
+pub fn random(seed: u32, min: f32, max: f32, data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    if let Some(value) = next(seed, data, inputs[0]) {
+        data[VALUE] = min + (max - min) * value;
+    }
+    outputs[0] = data[VALUE];
+}
+
+/// Lets a trigger through with the probability of the second input
+pub fn chance(seed: u32, data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    outputs[0] = match next(seed, data, inputs[0]) {
+        Some(value) if value < inputs[1] => 1.0,
+        _ => 0.0,
+    };
+}
+
+/// Moves by up to the second input up or down for every trigger, 
+/// and stays between ``min`` and ``max``
+pub fn random_walk(seed: u32, min: f32, max: f32, data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    if let Some(value) = next(seed, data, inputs[0]) {
+        data[VALUE] = (data[VALUE] + (value * 2.0 - 1.0) * inputs[1]).clamp(min, max);
+    }
+    outputs[0] = data[VALUE];
+}
+
+/// Picks one of the inputs after the trigger for every trigger.
+/// The value in the data is which one.
+pub fn choose(seed: u32, data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    let n_choices = inputs.len() - 1;
+    if let Some(value) = next(seed, data, inputs[0]) {
+        data[VALUE] = ((value * n_choices as f32) as usize).min(n_choices - 1) as f32;
+    }
+    outputs[0] = inputs[1 + data[VALUE] as usize];
+}
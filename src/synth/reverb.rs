@@ -0,0 +1,126 @@
+// A reverb in the style of Freeverb, with 8 comb filters and 4 allpass
+// filters for each channel, and a pre-delay before them.
+//
+// The data looks like this:
+// [pre-delay position, comb positions, comb filter values, allpass positions],
+// and the buffer has the pre-delay first, then the combs, then the allpasses.
+// Where the lines are in the buffer is worked out when the patch is compiled.
+
+const N_COMBS: usize = 8;
+const N_ALLPASSES: usize = 4;
+const N_LINES: usize = 1 + 2 * (N_COMBS + N_ALLPASSES);
+
+// The lengths of the lines in samples at 44100 hz. The right channel has
+// slightly longer lines, so that the channels are different.
+const COMB_LENGTHS: [usize; N_COMBS] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_LENGTHS: [usize; N_ALLPASSES] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+
+/// The longest pre-delay, in seconds
+pub const MAX_PRE_DELAY: f32 = 0.5;
+
+const INPUT_GAIN: f32 = 0.015;
+const WET_GAIN: f32 = 3.0;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+const COMB_POSITIONS: usize = 1;
+const COMB_FILTERS: usize = COMB_POSITIONS + 2 * N_COMBS;
+const ALLPASS_POSITIONS: usize = COMB_FILTERS + 2 * N_COMBS;
+pub const N_DATA: usize = ALLPASS_POSITIONS + 2 * N_ALLPASSES;
+
+/// The lengths of all the lines, in the order they are in the buffer
+fn line_lengths(sample_rate: f32) -> [usize; N_LINES] {
+    let scale = |length: usize| ((length as f32 * sample_rate / 44100.0) as usize).max(1);
+    let mut lengths = [0; N_LINES];
+    lengths[0] = ((MAX_PRE_DELAY * sample_rate) as usize).max(1);
+    for channel in 0..2 {
+        let spread = channel * STEREO_SPREAD;
+        for (i, &length) in COMB_LENGTHS.iter().enumerate() {
+            lengths[1 + channel * N_COMBS + i] = scale(length + spread);
+        }
+        for (i, &length) in ALLPASS_LENGTHS.iter().enumerate() {
+            lengths[1 + 2 * N_COMBS + channel * N_ALLPASSES + i] = scale(length + spread);
+        }
+    }
+    lengths
+}
+
+/// Where the lines start in the buffer and how long they are,
+/// for the sample rate the reverb runs at
+#[derive(Debug, PartialEq)]
+pub struct ReverbLines {
+    starts: [usize; N_LINES],
+    lengths: [usize; N_LINES],
+}
+
+impl ReverbLines {
+    pub fn new(sample_rate: f32) -> ReverbLines {
+        let lengths = line_lengths(sample_rate);
+        let mut starts = [0; N_LINES];
+        for i in 1..N_LINES {
+            starts[i] = starts[i - 1] + lengths[i - 1];
+        }
+        ReverbLines { starts, lengths }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.lengths.iter().sum()
+    }
+}
+
+/// Reads the next value out of a line and moves the position forward,
+/// returning the line and the value, so that a new value can be put there.
+#[inline]
+fn step<'a>(buffer: &'a mut [f32], start: usize, length: usize, position: &mut f32) -> (&'a mut f32, f32) {
+    let index = (*position as usize) % length;
+    *position = ((index + 1) % length) as f32;
+    let slot = &mut buffer[start + index];
+    let value = *slot;
+    (slot, value)
+}
+
+/// The inputs are the signal, room size, damping, pre-delay and wet/dry mix.
+/// The outputs are left and right.
+pub fn evaluate(lines: &ReverbLines,
+                data: &mut [f32],
+                buffer: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32],
+                dt_per_sample: f32) {
+    let sample_rate = 1.0 / dt_per_sample;
+    let ReverbLines { starts, lengths } = lines;
+
+    let signal = inputs[0];
+    let feedback = inputs[1].clamp(0.0, 1.0) * 0.28 + 0.7;
+    let damping = inputs[2].clamp(0.0, 1.0) * 0.4;
+    let mix = inputs[4].clamp(0.0, 1.0);
+
+    // The pre-delay is written first and then read from behind
+    let pre_delay = (inputs[3].clamp(0.0, MAX_PRE_DELAY) * sample_rate) as usize;
+    let write = data[0] as usize % lengths[0];
+    buffer[write] = signal;
+    let delayed = buffer[(write + lengths[0] - pre_delay.min(lengths[0] - 1)) % lengths[0]];
+    data[0] = ((write + 1) % lengths[0]) as f32;
+
+    let input = delayed * INPUT_GAIN;
+    for (channel, output) in outputs.iter_mut().enumerate().take(2) {
+        let mut out = 0.0;
+        for i in 0..N_COMBS {
+            let line = 1 + channel * N_COMBS + i;
+            let (slot, value) = step(buffer, starts[line], lengths[line], &mut data[COMB_POSITIONS + channel * N_COMBS + i]);
+            let filter = &mut data[COMB_FILTERS + channel * N_COMBS + i];
+            *filter = value * (1.0 - damping) + *filter * damping;
+            *slot = input + *filter * feedback;
+            out += value;
+        }
+
+        for i in 0..N_ALLPASSES {
+            let line = 1 + 2 * N_COMBS + channel * N_ALLPASSES + i;
+            let (slot, value) = step(buffer, starts[line], lengths[line], &mut data[ALLPASS_POSITIONS + channel * N_ALLPASSES + i]);
+            *slot = out + value * ALLPASS_FEEDBACK;
+            out = value - out;
+        }
+
+        *output = signal * (1.0 - mix) + out * WET_GAIN * mix;
+    }
+}
@@ -0,0 +1,99 @@
+// Playing back samples that were loaded from files.
+//
+// The data of a sample player looks like this:
+// [position, fraction of position, playing, previous trigger].
+// The position is split in two so that long samples don't lose precision.
+
+pub struct SampleData {
+    pub samples: Vec<f32>,
+    pub sample_rate: f32,
+}
+
+// Samples can be very long, so they are not printed
+impl std::fmt::Debug for SampleData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SampleData ( length: {}, sample_rate: {} )", self.samples.len(), self.sample_rate)
+    }
+}
+
+impl PartialEq for SampleData {
+    fn eq(&self, other: &SampleData) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl SampleData {
+    /// Gets the sample at a fractional position, interpolating
+    /// between the samples around it. When looping, the sample after
+    /// the end of the loop is the start of the loop.
+    #[inline]
+    pub fn get(&self, position: usize, fraction: f32, looping: Option<(f32, f32)>) -> f32 {
+        let mut next = position as f32 + 1.0;
+        if let Some((loop_start, loop_end)) = looping {
+            if next >= loop_end {
+                next -= loop_end - loop_start;
+            }
+        }
+        let a = self.samples.get(position).copied().unwrap_or(0.0);
+        let b = self.samples.get(next as usize).copied().unwrap_or(0.0);
+        a + (b - a) * fraction
+    }
+}
+
+pub const N_DATA: usize = 4;
+
+const POSITION: usize = 0;
+const FRACTION: usize = 1;
+const PLAYING: usize = 2;
+const PREV_TRIGGER: usize = 3;
+
+/// The start and loop points are in samples. Without a start, the sample
+/// starts from the end if the rate is negative when it's triggered.
+/// The inputs are the trigger and the rate.
+pub fn evaluate(sample: &SampleData,
+                start: Option<f32>,
+                looping: Option<(f32, f32)>,
+                data: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32],
+                dt_per_sample: f32) {
+    if inputs[0] > 0.0 && data[PREV_TRIGGER] <= 0.0 {
+        let start = match start {
+            Some(start) => start,
+            None if inputs[1] < 0.0 => (sample.samples.len() as f32 - 1.0).max(0.0),
+            None => 0.0,
+        };
+        data[POSITION] = start.floor();
+        data[FRACTION] = start - start.floor();
+        data[PLAYING] = 1.0;
+    }
+    data[PREV_TRIGGER] = inputs[0];
+
+    if data[PLAYING] <= 0.0 {
+        outputs[0] = 0.0;
+        return;
+    }
+
+    outputs[0] = sample.get(data[POSITION] as usize, data[FRACTION], looping);
+
+    let step = data[FRACTION] + inputs[1] * sample.sample_rate * dt_per_sample;
+    let mut position = data[POSITION] + step.floor();
+    data[FRACTION] = step - step.floor();
+
+    match looping {
+        Some((loop_start, loop_end)) => {
+            let length = loop_end - loop_start;
+            if position >= loop_end {
+                position -= ((position - loop_start) / length).floor() * length;
+            }else if position < loop_start && inputs[1] < 0.0 {
+                position += ((loop_end - position) / length).floor() * length;
+            }
+        },
+        None => {
+            if position < 0.0 || position >= sample.samples.len() as f32 {
+                data[PLAYING] = 0.0;
+            }
+        },
+    }
+    data[POSITION] = position;
+}
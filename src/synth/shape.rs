@@ -0,0 +1,104 @@
+// Waveshaping and distortion.
+//
+// The shapers can be oversampled, so that the harmonics they make don't
+// alias as much. Then the data is [up position, down position, dc blocker
+// input, dc blocker output], and the buffer is the history of the up and
+// down sampling, and space for the samples at the high sample rate.
+
+use std::rc::Rc;
+use super::oversample::Oversampler;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Tanh,
+    /// A cubic curve that is flat at 1 and -1
+    SoftClip,
+    /// Folds the signal back down when it goes above 1 or below -1
+    Foldback,
+    /// Like tanh, but more on one side than the other, like a tube
+    Tube,
+}
+
+pub const N_DATA: usize = 4;
+
+/// How fast the dc blocker of the tube gets rid of offsets
+const DC_BLOCK: f32 = 0.995;
+
+impl Shape {
+    #[inline]
+    fn apply(self, x: f32, bias: f32) -> f32 {
+        match self {
+            Shape::Tanh => x.tanh(),
+            Shape::SoftClip => {
+                let x = x.clamp(-1.0, 1.0);
+                1.5 * x - 0.5 * x * x * x
+            },
+            Shape::Foldback => ((x - 1.0).rem_euclid(4.0) - 2.0).abs() - 1.0,
+            Shape::Tube => (x + bias).tanh() - bias.tanh(),
+        }
+    }
+}
+
+pub fn buffer_size(oversampler: &Option<Rc<Oversampler>>) -> usize {
+    match oversampler {
+        Some(o) => o.up_size() + o.down_size() + o.factor,
+        None => 0,
+    }
+}
+
+/// The inputs are the signal, how much to amplify it before shaping it,
+/// and the bias, which only the tube uses
+pub fn evaluate(shape: Shape,
+                oversampler: &Option<Rc<Oversampler>>,
+                data: &mut [f32],
+                buffer: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32]) {
+    let drive = inputs[1];
+    let bias = inputs[2];
+
+    let mut output = match oversampler {
+        Some(oversampler) => {
+            let (up, rest) = buffer.split_at_mut(oversampler.up_size());
+            let (down, work) = rest.split_at_mut(oversampler.down_size());
+            let (up_position, rest) = data.split_at_mut(1);
+            oversampler.upsample(up, &mut up_position[0], inputs[0], work);
+            for value in work.iter_mut() {
+                *value = shape.apply(*value * drive, bias);
+            }
+            oversampler.downsample(down, &mut rest[0], work)
+        },
+        None => shape.apply(inputs[0] * drive, bias),
+    };
+
+    // The tube makes a dc offset, which isn't nice to have around
+    if shape == Shape::Tube {
+        let blocked = output - data[2] + DC_BLOCK * data[3];
+        data[2] = output;
+        data[3] = blocked;
+        output = blocked;
+    }
+
+    outputs[0] = output;
+}
+
+/// Rounds the signal to fewer bits. The inputs are the signal and the
+/// number of bits, which doesn't have to be a whole number.
+#[inline]
+pub fn bitcrush(outputs: &mut [f32], inputs: &[f32]) {
+    let steps = 2f32.powf(inputs[1].max(1.0) - 1.0);
+    outputs[0] = (inputs[0] * steps).round() / steps;
+}
+
+/// Holds the signal to make it sound like it has a lower sample rate.
+/// The inputs are the signal and the sample rate, and the data
+/// is [phase, held value].
+#[inline]
+pub fn decimate(data: &mut [f32], outputs: &mut [f32], inputs: &[f32], dt_per_sample: f32) {
+    data[0] += inputs[1].abs() * dt_per_sample;
+    if data[0] >= 1.0 {
+        data[0] %= 1.0;
+        data[1] = inputs[0];
+    }
+    outputs[0] = data[1];
+}
@@ -0,0 +1,251 @@
+// Spectral effects, which work on a short time fourier transform of the signal.
+//
+// The signal is cut into frames of ``size`` samples that overlap by three
+// quarters, so a new frame starts every ``size / 4`` samples. Every frame is
+// windowed and transformed, changed by the effect, transformed back and then
+// windowed again and added to the output. Gathering a whole frame before it
+// can be worked on makes the output ``size`` samples late.
+//
+// The data is [position, samples since the last frame, the control input at
+// the last frame], and the buffer is laid out like this:
+// [input line of every signal, output,
+//  real and imaginary spectrum of every signal, what the effect keeps per bin]
+
+use std::f32::consts::PI;
+use super::fft::fft;
+use crate::operator::amp_to_db;
+
+/// How many frames overlap each other
+const OVERLAP: usize = 4;
+
+/// The hann windows of the frames add up to this with an overlap of 4,
+/// since every sample is windowed twice
+const WINDOW_SUM: f32 = 1.5;
+
+/// The vocoder smooths the spectra over ``size / VOCODER_SMOOTHING`` bins
+/// on each side, to find the shape of the sound and not the notes in it
+const VOCODER_SMOOTHING: usize = 256;
+
+pub const N_DATA: usize = 3;
+
+const POSITION: usize = 0;
+const SINCE_FRAME: usize = 1;
+const CONTROL: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    Freeze,
+    Gate,
+    PitchShift,
+    Vocoder,
+}
+
+impl Effect {
+    /// How many of the inputs are signals that are transformed
+    fn n_signals(self) -> usize {
+        match self {
+            Effect::Vocoder => 2,
+            _ => 1,
+        }
+    }
+
+    /// How many numbers the effect keeps for every bin
+    fn n_bin_state(self) -> usize {
+        match self {
+            // The last phase, the frozen magnitude, how much the frozen phase
+            // moves every frame, and the frozen phase
+            Effect::Freeze => 4,
+            Effect::Gate => 0,
+            // The last phase, the phase of the output, and the magnitude and
+            // frequency of the output
+            Effect::PitchShift => 4,
+            // The smoothed magnitudes of the carrier and the modulator
+            Effect::Vocoder => 2,
+        }
+    }
+}
+
+pub fn buffer_size(effect: Effect, size: usize) -> usize {
+    let signals = effect.n_signals();
+    signals * size + size + 2 * signals * size + effect.n_bin_state() * (size / 2 + 1)
+}
+
+/// A periodic hann window
+#[inline]
+fn window(i: usize, size: usize) -> f32 {
+    0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos()
+}
+
+/// Wraps a phase to be from -pi to pi
+#[inline]
+fn wrap_phase(phase: f32) -> f32 {
+    (phase + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// The first input is the signal, and the second one depends on the effect:
+/// * Freeze: the spectrum is held while it's above 0
+/// * Gate: the threshold in decibels that bins have to be above to be let through
+/// * PitchShift: how many semitones to shift by
+/// * Vocoder: the modulator, while the signal is the carrier
+pub fn evaluate(effect: Effect,
+                size: usize,
+                data: &mut [f32],
+                buffer: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32]) {
+    let signals = effect.n_signals();
+    let (lines, rest) = buffer.split_at_mut(signals * size);
+    let (output, rest) = rest.split_at_mut(size);
+    let (spectra, state) = rest.split_at_mut(2 * signals * size);
+
+    let position = data[POSITION] as usize % size;
+    for (line, &input) in lines.chunks_exact_mut(size).zip(inputs) {
+        line[position] = input;
+    }
+    outputs[0] = output[position];
+    output[position] = 0.0;
+    data[POSITION] = ((position + 1) % size) as f32;
+
+    data[SINCE_FRAME] += 1.0;
+    if (data[SINCE_FRAME] as usize) < size / OVERLAP {
+        return;
+    }
+    data[SINCE_FRAME] = 0.0;
+
+    // The oldest sample in the lines is right after the newest one
+    for (line, spectrum) in lines.chunks_exact(size).zip(spectra.chunks_exact_mut(2 * size)) {
+        let (re, im) = spectrum.split_at_mut(size);
+        for (i, (re, im)) in re.iter_mut().zip(im.iter_mut()).enumerate() {
+            *re = line[(position + 1 + i) % size] * window(i, size);
+            *im = 0.0;
+        }
+        fft(re, im, false);
+    }
+
+    let (spectrum, others) = spectra.split_at_mut(2 * size);
+    let (re, im) = spectrum.split_at_mut(size);
+    let bins = size / 2 + 1;
+    match effect {
+        Effect::Freeze => freeze(inputs[1] > 0.0, data[CONTROL] > 0.0, &mut re[..bins], &mut im[..bins], state),
+        Effect::Gate => gate(inputs[1], size, &mut re[..bins], &mut im[..bins]),
+        Effect::PitchShift => pitch_shift(2f32.powf(inputs[1] / 12.0), &mut re[..bins], &mut im[..bins], state),
+        Effect::Vocoder => vocoder(size, &mut re[..bins], &mut im[..bins], others, state),
+    }
+    data[CONTROL] = inputs[1];
+
+    // The second half of the spectrum of a real signal mirrors the first
+    im[0] = 0.0;
+    im[size / 2] = 0.0;
+    for k in 1..size / 2 {
+        re[size - k] = re[k];
+        im[size - k] = -im[k];
+    }
+    fft(re, im, true);
+
+    for (i, value) in re.iter().enumerate() {
+        output[(position + 1 + i) % size] += value * window(i, size) / WINDOW_SUM;
+    }
+}
+
+fn freeze(frozen: bool, was_frozen: bool, re: &mut [f32], im: &mut [f32], state: &mut [f32]) {
+    let bins = re.len();
+    let (last_phase, rest) = state.split_at_mut(bins);
+    let (magnitudes, rest) = rest.split_at_mut(bins);
+    let (phase_steps, phases) = rest.split_at_mut(bins);
+
+    for k in 0..bins {
+        let phase = im[k].atan2(re[k]);
+        if frozen && !was_frozen {
+            magnitudes[k] = re[k].hypot(im[k]);
+            phase_steps[k] = phase - last_phase[k];
+            phases[k] = phase;
+        }
+        last_phase[k] = phase;
+
+        if frozen {
+            phases[k] = wrap_phase(phases[k] + phase_steps[k]);
+            re[k] = magnitudes[k] * phases[k].cos();
+            im[k] = magnitudes[k] * phases[k].sin();
+        }
+    }
+}
+
+fn gate(threshold: f32, size: usize, re: &mut [f32], im: &mut [f32]) {
+    // A sine wave with an amplitude of 1 has a magnitude of size / 4 in the
+    // bin it's in, because of the window, so that is 0 decibels
+    let scale = 4.0 / size as f32;
+    for (re, im) in re.iter_mut().zip(im.iter_mut()) {
+        if amp_to_db(re.hypot(*im) * scale) < threshold {
+            *re = 0.0;
+            *im = 0.0;
+        }
+    }
+}
+
+/// A phase vocoder, which finds the exact frequency of every bin from how much
+/// its phase moved since the last frame, and moves it to another bin
+fn pitch_shift(ratio: f32, re: &mut [f32], im: &mut [f32], state: &mut [f32]) {
+    let bins = re.len();
+    let (last_phase, rest) = state.split_at_mut(bins);
+    let (phases, rest) = rest.split_at_mut(bins);
+    let (magnitudes, freqs) = rest.split_at_mut(bins);
+
+    // How much the phase of every bin moves every frame, per bin
+    let expected = 2.0 * PI / OVERLAP as f32;
+
+    magnitudes.iter_mut().for_each(|v| *v = 0.0);
+    freqs.iter_mut().for_each(|v| *v = 0.0);
+    for k in 0..bins {
+        let phase = im[k].atan2(re[k]);
+        let moved = wrap_phase(phase - last_phase[k] - k as f32 * expected);
+        last_phase[k] = phase;
+
+        let target = (k as f32 * ratio).round() as usize;
+        if target < bins {
+            magnitudes[target] += re[k].hypot(im[k]);
+            freqs[target] = (k as f32 + moved / expected) * ratio;
+        }
+    }
+
+    for k in 0..bins {
+        phases[k] = wrap_phase(phases[k] + freqs[k] * expected);
+        re[k] = magnitudes[k] * phases[k].cos();
+        im[k] = magnitudes[k] * phases[k].sin();
+    }
+}
+
+/// Gives the carrier the shape of the modulator, by dividing it by its
+/// own smoothed spectrum and multiplying it by the one of the modulator
+fn vocoder(size: usize, re: &mut [f32], im: &mut [f32], modulator: &mut [f32], state: &mut [f32]) {
+    let bins = re.len();
+    let (carrier_shape, modulator_shape) = state.split_at_mut(bins);
+    let (modulator_re, rest) = modulator.split_at_mut(size);
+    let modulator_im = &rest[..size];
+
+    for k in 0..bins {
+        carrier_shape[k] = re[k].hypot(im[k]);
+        modulator_shape[k] = modulator_re[k].hypot(modulator_im[k]);
+    }
+
+    // The real part of the modulator isn't needed anymore, so it's used
+    // for the running sums of the smoothing
+    let width = (size / VOCODER_SMOOTHING).max(1);
+    for shape in [&mut *carrier_shape, &mut *modulator_shape] {
+        let sums = &mut modulator_re[..bins + 1];
+        sums[0] = 0.0;
+        for k in 0..bins {
+            sums[k + 1] = sums[k] + shape[k];
+        }
+        for (k, value) in shape.iter_mut().enumerate() {
+            let start = k.saturating_sub(width);
+            let end = (k + width + 1).min(bins);
+            *value = (sums[end] - sums[start]) / (end - start) as f32;
+        }
+    }
+
+    for k in 0..bins {
+        let gain = modulator_shape[k] / carrier_shape[k].max(1e-6);
+        re[k] *= gain;
+        im[k] *= gain;
+    }
+}
@@ -0,0 +1,50 @@
+// Stereo nodes: panning, stereo width and mixing.
+//
+// Panning uses the equal power law, so that a sound is as loud in the
+// middle as it is on one side. The width is changed by turning the
+// left and right channels into mid and side channels, and turning
+// the side up or down.
+
+use std::f32::consts::FRAC_PI_4;
+
+/// How loud the left and right channel are at ``position``,
+/// which goes from -1, all the way left, to 1, all the way right.
+#[inline]
+fn pan_gains(position: f32) -> (f32, f32) {
+    let angle = (position.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// The inputs are the signal and the position, and the outputs are left and right
+pub fn pan(outputs: &mut [f32], inputs: &[f32]) {
+    let (left, right) = pan_gains(inputs[1]);
+    outputs[0] = inputs[0] * left;
+    outputs[1] = inputs[0] * right;
+}
+
+/// The inputs are left, right and the width, where 0 is mono, 1 leaves
+/// it as it is and more than 1 makes it wider
+pub fn width(outputs: &mut [f32], inputs: &[f32]) {
+    let mid = (inputs[0] + inputs[1]) * 0.5;
+    let side = (inputs[0] - inputs[1]) * 0.5 * inputs[2];
+    outputs[0] = mid + side;
+    outputs[1] = mid - side;
+}
+
+/// Adds up the inputs times their gains
+pub fn mix(gains: &[f32], outputs: &mut [f32], inputs: &[f32]) {
+    outputs[0] = gains.iter().zip(inputs).map(|(gain, input)| gain * input).sum();
+}
+
+/// Like mix, but every input is also panned, and the outputs are left and right
+pub fn stereo_mix(channels: &[(f32, f32)], outputs: &mut [f32], inputs: &[f32]) {
+    let mut left = 0.0;
+    let mut right = 0.0;
+    for (&(gain, position), input) in channels.iter().zip(inputs) {
+        let (left_gain, right_gain) = pan_gains(position);
+        left += input * gain * left_gain;
+        right += input * gain * right_gain;
+    }
+    outputs[0] = left;
+    outputs[1] = right;
+}
@@ -0,0 +1,88 @@
+// Nodes that make and change triggers and gates. A trigger is 1 for one
+// sample, and things are triggered when their input goes above 0.
+
+use crate::operator::bool_to_float;
+
+#[inline]
+pub fn is_rising(value: f32, prev: f32) -> bool {
+    value > 0.0 && prev <= 0.0
+}
+
+/// The data is [previous input]
+pub fn rising_edge(data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    outputs[0] = bool_to_float(is_rising(inputs[0], data[0]));
+    data[0] = inputs[0];
+}
+
+/// The data is [previous input]
+pub fn falling_edge(data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    outputs[0] = bool_to_float(inputs[0] <= 0.0 && data[0] > 0.0);
+    data[0] = inputs[0];
+}
+
+/// Counts triggers, and goes back to 0 when reaching ``max``, unless it's 0.
+/// The inputs are the trigger and a reset, and the data is 
+/// [count, previous trigger, previous reset].
+pub fn counter(max: f32, data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    if is_rising(inputs[0], data[1]) {
+        data[0] += 1.0;
+        if max > 0.0 {
+            data[0] %= max;
+        }
+    }
+    if is_rising(inputs[1], data[2]) {
+        data[0] = 0.0;
+    }
+    data[1] = inputs[0];
+    data[2] = inputs[1];
+    outputs[0] = data[0];
+}
+
+/// Lets every ``n``th trigger through, starting with the first one.
+/// The data is [triggers since the last one let through, previous trigger].
+pub fn clock_div(n: u32, data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    outputs[0] = 0.0;
+    if is_rising(inputs[0], data[1]) {
+        if data[0] == 0.0 {
+            outputs[0] = 1.0;
+        }
+        data[0] = (data[0] + 1.0) % n as f32;
+    }
+    data[1] = inputs[0];
+}
+
+/// Spreads ``pulses`` hits as evenly as possible over ``steps`` steps,
+/// and rotates the pattern to the left by ``rotate`` steps
+pub fn euclid_pattern(steps: usize, pulses: usize, rotate: usize) -> Vec<bool> {
+    (0..steps).map(|i| {
+        let i = (i + rotate) % steps;
+        (i * pulses) % steps < pulses
+    }).collect()
+}
+
+/// Moves one step for every trigger, and outputs a trigger and a
+/// gate that lasts for the whole step when the step is a hit. The
+/// data is [next step, previous trigger, gate].
+pub fn euclid(pattern: &[bool], data: &mut [f32], outputs: &mut [f32], inputs: &[f32]) {
+    outputs[0] = 0.0;
+    if is_rising(inputs[0], data[1]) {
+        let step = data[0] as usize % pattern.len();
+        let hit = pattern[step];
+        outputs[0] = bool_to_float(hit);
+        data[2] = bool_to_float(hit);
+        data[0] = ((step + 1) % pattern.len()) as f32;
+    }
+    data[1] = inputs[0];
+    outputs[1] = data[2];
+}
+
+/// Turns a trigger into a gate that is ``length`` seconds long. The inputs
+/// are the trigger and the length, and the data is [time left, previous trigger].
+pub fn pulse(data: &mut [f32], outputs: &mut [f32], inputs: &[f32], dt_per_sample: f32) {
+    if is_rising(inputs[0], data[1]) {
+        data[0] = inputs[1];
+    }
+    data[1] = inputs[0];
+    outputs[0] = bool_to_float(data[0] > 0.0);
+    data[0] -= dt_per_sample;
+}
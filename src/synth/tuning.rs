@@ -0,0 +1,214 @@
+// Tunings, i.e. how midi note numbers turn into frequencies.
+//
+// Tunings are read from Scala files. A scale file (.scl) lists
+// the pitches of a scale, and a keyboard mapping file (.kbm) says
+// which notes play which pitches of the scale. See
+// http://www.huygens-fokker.org/scala/scl_format.html and
+// http://www.huygens-fokker.org/scala/help.htm#mappings
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Tuning {
+    // The pitches of the scale in cents, starting from the one
+    // after the unison. The last one is the period of the scale.
+    scale: Vec<f32>,
+
+    // The degrees of the scale that the notes in a period of
+    // the keyboard play, starting from the middle note. None means
+    // that the note doesn't play anything. If it's empty, every
+    // note plays the next degree of the scale.
+    mapping: Vec<Option<usize>>,
+    middle_note: i32,
+    reference_note: i32,
+    reference_freq: f32,
+    // The degree of the scale that the mapping repeats at
+    octave_degree: usize,
+}
+
+impl Tuning {
+    /// Twelve tone equal temperament, with A4 at 440 hz
+    pub fn equal_temperament() -> Tuning {
+        Tuning {
+            scale: (1..=12).map(|v| v as f32 * 100.0).collect(),
+            mapping: Vec::new(),
+            middle_note: 60,
+            reference_note: 69,
+            reference_freq: 440.0,
+            octave_degree: 12,
+        }
+    }
+
+    /// Reads the contents of a scale file and a keyboard mapping file.
+    /// Without a scale, the scale is twelve tone equal temperament.
+    /// Without a keyboard mapping, note 60 is the first note of the scale, and
+    /// the scale is tuned so that note 69 is 440 hz.
+    pub fn from_scala(scale: Option<&str>, keyboard_mapping: Option<&str>) -> Result<Tuning, String> {
+        let mut tuning = Tuning::equal_temperament();
+
+        if let Some(scale) = scale {
+            let mut lines = scala_lines(scale);
+            // The first line is a description
+            lines.next().ok_or("Missing description")?;
+            let n_notes: usize = parse_value(lines.next(), "Missing number of notes")?;
+            let mut pitches = Vec::with_capacity(n_notes);
+            for _ in 0..n_notes {
+                let pitch = lines.next().ok_or("Missing pitch")?;
+                pitches.push(parse_pitch(pitch).ok_or_else(|| format!("Invalid pitch '{}'", pitch))?);
+            }
+
+            if pitches.is_empty() {
+                return Err("The scale has no pitches".to_string());
+            }
+
+            tuning.octave_degree = pitches.len();
+            tuning.scale = pitches;
+        }
+
+        if let Some(keyboard_mapping) = keyboard_mapping {
+            let mut lines = scala_lines(keyboard_mapping);
+            let map_size: usize = parse_value(lines.next(), "Missing map size")?;
+            // The range of notes to retune, we retune all of them
+            let _first_note: i32 = parse_value(lines.next(), "Missing first note")?;
+            let _last_note: i32 = parse_value(lines.next(), "Missing last note")?;
+            tuning.middle_note = parse_value(lines.next(), "Missing middle note")?;
+            tuning.reference_note = parse_value(lines.next(), "Missing reference note")?;
+            tuning.reference_freq = parse_value(lines.next(), "Missing reference frequency")?;
+            tuning.octave_degree = parse_value(lines.next(), "Missing octave degree")?;
+
+            for _ in 0..map_size {
+                // Mappings may be left out at the end, which means they're unmapped
+                let degree = match lines.next() {
+                    Some(line) if !line.starts_with('x') => 
+                        Some(line.parse().map_err(|_| format!("Invalid mapping '{}'", line))?),
+                    _ => None,
+                };
+                tuning.mapping.push(degree);
+            }
+
+            if tuning.mapping.is_empty() {
+                tuning.octave_degree = tuning.scale.len();
+            }
+        }
+
+        Ok(tuning)
+    }
+
+    /// The frequency of a note, fractional notes are between the two notes
+    /// around them. Notes that aren't mapped to anything have a frequency of 0.
+    pub fn midi_to_freq(&self, midi: f32) -> f32 {
+        let below = midi.floor();
+        let fraction = midi - below;
+        let below_cents = self.cents_from_reference(below as i32);
+        let cents = if fraction > 0.0 {
+            match (below_cents, self.cents_from_reference(below as i32 + 1)) {
+                (Some(a), Some(b)) => Some(a + (b - a) * fraction),
+                (a, b) => a.or(b),
+            }
+        }else{
+            below_cents
+        };
+
+        match cents {
+            Some(cents) => self.reference_freq * (cents / 1200.0).exp2(),
+            None => 0.0,
+        }
+    }
+
+    /// The (fractional) note that plays a frequency. Notes that aren't mapped
+    /// to anything are skipped, so a frequency between two mapped notes is
+    /// between their numbers. If nothing can play the frequency it's 0.
+    pub fn freq_to_midi(&self, freq: f32) -> f32 {
+        let reference = match self.cents_from_middle(self.reference_note) {
+            Some(reference) if freq > 0.0 => reference,
+            _ => return 0.0,
+        };
+        let cents = 1200.0 * (freq / self.reference_freq).log2() + reference;
+
+        // The number of notes in a period of the keyboard, and how high the period is
+        let (size, period_cents) = if self.mapping.is_empty() {
+            (self.scale.len() as i32, self.degree_cents(self.scale.len() as i32))
+        }else{
+            (self.mapping.len() as i32, self.degree_cents(self.octave_degree as i32))
+        };
+
+        let first = (0..size).find_map(|key| Some((key, self.cents_from_middle(self.middle_note + key)?)));
+        let (first_key, first_cents) = match first {
+            Some(first) => first,
+            None => return 0.0,
+        };
+        if period_cents <= 0.0 {
+            return (self.middle_note + first_key) as f32;
+        }
+
+        // Find the period the frequency is in, and then the notes around it in that period
+        let period = ((cents - first_cents) / period_cents).floor();
+        let cents = cents - period * period_cents;
+        let start = (self.middle_note + first_key) as f32 + period * size as f32;
+        let mut below = (first_key, first_cents);
+        for key in first_key + 1..=first_key + size {
+            if let Some(key_cents) = self.cents_from_middle(self.middle_note + key) {
+                if key_cents > cents {
+                    let fraction = (cents - below.1) / (key_cents - below.1);
+                    return start + (below.0 - first_key) as f32 + fraction * (key - below.0) as f32;
+                }
+                below = (key, key_cents);
+            }
+        }
+        start + (below.0 - first_key) as f32
+    }
+
+    fn cents_from_reference(&self, note: i32) -> Option<f32> {
+        Some(self.cents_from_middle(note)? - self.cents_from_middle(self.reference_note)?)
+    }
+
+    fn cents_from_middle(&self, note: i32) -> Option<f32> {
+        let offset = note - self.middle_note;
+        if self.mapping.is_empty() {
+            return Some(self.degree_cents(offset));
+        }
+
+        let size = self.mapping.len() as i32;
+        let period = offset.div_euclid(size);
+        let degree = self.mapping[offset.rem_euclid(size) as usize]?;
+        Some(period as f32 * self.degree_cents(self.octave_degree as i32) + self.degree_cents(degree as i32))
+    }
+
+    /// Degrees past the end of the scale go on into the next period
+    fn degree_cents(&self, degree: i32) -> f32 {
+        let size = self.scale.len() as i32;
+        let period = degree.div_euclid(size);
+        let step = degree.rem_euclid(size) as usize;
+        let cents = if step == 0 { 0.0 } else { self.scale[step - 1] };
+        period as f32 * self.scale[self.scale.len() - 1] + cents
+    }
+}
+
+/// The lines of a scala file without the comments. Only the
+/// first word of a line is used, the rest of it is ignored.
+fn scala_lines(contents: &str) -> impl Iterator<Item = &str> {
+    contents.lines()
+        .filter(|line| !line.starts_with('!'))
+        .map(|line| line.split_whitespace().next().unwrap_or(""))
+}
+
+fn parse_value<T: std::str::FromStr>(line: Option<&str>, missing: &str) -> Result<T, String> {
+    let line = line.ok_or(missing)?;
+    line.parse().map_err(|_| format!("Invalid value '{}'", line))
+}
+
+/// Pitches with a dot are in cents, the others are ratios
+fn parse_pitch(pitch: &str) -> Option<f32> {
+    if pitch.contains('.') {
+        return pitch.parse().ok();
+    }
+
+    let (numerator, denominator) = match pitch.find('/') {
+        Some(split) => (pitch[..split].parse::<f32>().ok()?, pitch[split + 1..].parse::<f32>().ok()?),
+        None => (pitch.parse().ok()?, 1.0),
+    };
+
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return None;
+    }
+
+    Some(1200.0 * (numerator / denominator).log2())
+}
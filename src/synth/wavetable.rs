@@ -0,0 +1,51 @@
+// Wavetable oscillators.
+//
+// The data is laid out like the data of an ``Oscillator``, i.e. just
+// the phase, from 0 to 1.
+
+use super::interpolate::{ self, Interpolation };
+
+/// A list of single cycle waveforms, which are called frames.
+/// The frames don't have to be the same length.
+#[derive(PartialEq)]
+pub struct WavetableData {
+    pub frames: Vec<Vec<f32>>,
+}
+
+// Wavetables can be very long, so they are not printed
+impl std::fmt::Debug for WavetableData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WavetableData ( frames: {} )", self.frames.len())
+    }
+}
+
+pub const N_DATA: usize = 1;
+
+/// The inputs are the frequency and the position, which morphs
+/// from the first frame at 0 to the last frame at 1.
+pub fn evaluate(table: &WavetableData,
+                interpolation: Interpolation,
+                data: &mut [f32],
+                outputs: &mut [f32],
+                inputs: &[f32],
+                dt_per_sample: f32) {
+    data[0] = (data[0] + inputs[0].abs() * dt_per_sample) % 1.0;
+    let phase = data[0];
+
+    let read = |frame: &[f32]| {
+        interpolation.read_wrapping(frame, phase * frame.len() as f32)
+    };
+
+    let last = table.frames.len() - 1;
+    let position = inputs[1].clamp(0.0, 1.0) * last as f32;
+    let frame = (position.floor() as usize).min(last);
+    let t = position - frame as f32;
+
+    // The frames are always crossfaded, the interpolation is
+    // only for reading between the samples of a frame
+    outputs[0] = if frame == last {
+        read(&table.frames[frame])
+    }else{
+        interpolate::linear(read(&table.frames[frame]), read(&table.frames[frame + 1]), t)
+    };
+}
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Reads a wave file one frame at a time, or all at once with ``read_all``
+pub struct WaveStream {
+    reader: hound::WavReader<BufReader<File>>,
+    channel: Option<usize>,
+    channels: usize,
+    // What integer samples are multiplied with to go from -1 to 1,
+    // or None if the samples are floats
+    scale: Option<f32>,
+}
+
+impl WaveStream {
+    /// Opens a wave file. If a channel is given only that channel is read,
+    /// otherwise all the channels are mixed together. Channels that aren't
+    /// in the file are silent, so check them with ``channels`` first.
+    pub fn open(path: impl AsRef<Path>, channel: Option<usize>) -> Result<WaveStream, hound::Error> {
+        let reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let channels = spec.channels as usize;
+
+        let scale = match spec.sample_format {
+            hound::SampleFormat::Float => None,
+            hound::SampleFormat::Int => Some(1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32),
+        };
+
+        Ok(WaveStream { reader, channel, channels, scale })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.reader.spec().sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// The number of frames left
+    pub fn len(&self) -> usize {
+        self.reader.len() as usize / self.channels
+    }
+
+    /// The next frame, which is one channel or all of them mixed
+    /// together, or None at the end of the file
+    pub fn next_frame(&mut self) -> Result<Option<f32>, hound::Error> {
+        let mut sum = 0.0;
+        for i in 0..self.channels {
+            let sample = match self.scale {
+                None => self.reader.samples::<f32>().next(),
+                Some(scale) => self.reader.samples::<i32>().next().map(|v| v.map(|v| v as f32 * scale)),
+            };
+            let sample = match sample {
+                Some(sample) => sample?,
+                None => return Ok(None),
+            };
+
+            match self.channel {
+                Some(channel) if channel == i => sum = sample,
+                Some(_) => (),
+                None => sum += sample,
+            }
+        }
+
+        match self.channel {
+            Some(_) => Ok(Some(sum)),
+            None => Ok(Some(sum / self.channels as f32)),
+        }
+    }
+
+    /// Reads the rest of the file into samples from -1 to 1,
+    /// and gives the sample rate of it
+    pub fn read_all(mut self) -> Result<(Vec<f32>, u32), hound::Error> {
+        let mut samples = Vec::with_capacity(self.len());
+        while let Some(sample) = self.next_frame()? {
+            samples.push(sample);
+        }
+
+        Ok((samples, self.sample_rate()))
+    }
+}